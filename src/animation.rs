@@ -0,0 +1,405 @@
+use crate::{
+    colour::Colour,
+    config::{AnimationFormat, Config},
+    encoder::AnimationEncoder,
+    fixed::Fixed,
+    view::View,
+};
+use anyhow::{Error, Result};
+use ndarray::Array2;
+use serde::Deserialize;
+use std::{fs, path::PathBuf, time::Instant};
+
+/// Animation described by the state of the fractal at given times
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct KeyframeFile {
+    /// Defaults to the frame rate of the configured animation
+    pub fps: Option<f64>,
+    #[serde(default = "default_interpolation")]
+    pub interpolation: Interpolation,
+    /// Whether the zoom is interpolated in log space, which makes its speed look constant
+    #[serde(default = "default_log_zoom")]
+    pub log_zoom: bool,
+    pub keyframes: Vec<Keyframe>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Interpolation {
+    Linear,
+    Smoothstep,
+    CatmullRom,
+}
+
+/// Values of the configuration at a point in time, anything missing keeps its previous value
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Keyframe {
+    /// Time of the keyframe in seconds
+    pub time: f64,
+    pub max_iterations: Option<usize>,
+    pub julia_c: Option<[f64; 2]>,
+    #[serde(default)]
+    pub view: KeyframeView,
+    #[serde(default)]
+    pub gradient: KeyframeGradient,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct KeyframeView {
+    pub center: Option<[Fixed; 2]>,
+    pub zoom: Option<f64>,
+    pub rotation: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct KeyframeGradient {
+    pub offset: Option<f64>,
+}
+
+/// Keyframe with every value filled in
+#[derive(Debug, Clone)]
+struct State {
+    time: f64,
+    center: (Fixed, Fixed),
+    zoom: f64,
+    rotation: f64,
+    max_iterations: f64,
+    julia_c: [f64; 2],
+    gradient_offset: Option<f64>,
+}
+
+fn default_interpolation() -> Interpolation {
+    Interpolation::CatmullRom
+}
+
+fn default_log_zoom() -> bool {
+    true
+}
+
+/// Renders a zoom from the configured view to the animation target as numbered frames
+pub fn zoom(config: &Config) -> Result<()> {
+    let frames = config.animation.frames.max(1);
+    let start = crate::render_view(View::initial(config), config);
+    let target = match &config.animation.center {
+        Some([re, im]) => (re.clone(), im.clone()),
+        None => start.center.clone(),
+    };
+    let end_scale_factor = start.scale_factor * config.view.zoom / config.animation.zoom;
+    println!(
+        "[{}] [ANIMATION] Rendering {} frames, zoom = {:e} to {:e}",
+        now!(),
+        frames,
+        config.view.zoom,
+        config.animation.zoom
+    );
+
+    write_frames(config, frames, config.animation.fps, |frame| {
+        let t = if frames > 1 {
+            (frame - 1) as f64 / (frames - 1) as f64
+        } else {
+            1.0
+        };
+        let view = interpolate(&start, &target, end_scale_factor, t);
+        render_frame(&view, config)
+    })
+}
+
+/// Renders the frames of a keyframe animation
+pub fn keyframes(animation: &KeyframeFile, config: &Config) -> Result<()> {
+    let fps = animation.fps.unwrap_or(config.animation.fps);
+    if fps <= 0.0 {
+        return Err(Error::msg("the frame rate should be positive"));
+    }
+    let states = resolve(animation, config)?;
+    let (first, last) = (&states[0], &states[states.len() - 1]);
+    let frames = ((last.time - first.time) * fps).floor() as usize + 1;
+    println!(
+        "[{}] [ANIMATION] Rendering {} frames from {} keyframes at {} fps",
+        now!(),
+        frames,
+        states.len(),
+        fps
+    );
+
+    write_frames(config, frames, fps, |frame| {
+        let time = first.time + (frame - 1) as f64 / fps;
+        let state = state_at(&states, animation, time);
+
+        let mut config = config.clone();
+        config.view.center = Some([state.center.0, state.center.1]);
+        config.view.zoom = state.zoom;
+        config.view.rotation = state.rotation;
+        config.max_iterations = (state.max_iterations.round() as usize).max(1);
+        config.julia_c = state.julia_c;
+        config.gradient.offset = state.gradient_offset;
+        let view = crate::render_view(View::initial(&config), &config);
+        render_frame(&view, &config)
+    })
+}
+
+/// Writes the frames of an animation, given the colours of each one and how they were made
+///
+/// PNG frames are numbered files in the render directory. Those which already exist are skipped,
+/// so an interrupted animation can be resumed by running it again. Other formats are encoded
+/// into a single file as the frames come, which can't be resumed.
+pub fn write_frames(
+    config: &Config,
+    frames: usize,
+    fps: f64,
+    frame_at: impl Fn(usize) -> Result<(Array2<Colour>, String)>,
+) -> Result<()> {
+    let began = Instant::now();
+    let format = config.animation.format;
+    let mut encoder = None;
+    let mut encoded = None;
+    if format != AnimationFormat::Png {
+        let extension = match format {
+            AnimationFormat::Apng => "apng",
+            AnimationFormat::Gif => "gif",
+            _ => "y4m",
+        };
+        let filepath = crate::timestamped_path(config).with_extension(extension);
+        let partial = filepath.with_extension(format!("{}.part", extension));
+        fs::create_dir_all(&config.render.directory)?;
+        encoder = Some(AnimationEncoder::new(
+            &partial,
+            &config.animation,
+            config.render.resolution,
+            frames,
+            fps,
+        )?);
+        encoded = Some((partial, filepath));
+    }
+
+    for frame in 1..=frames {
+        let filepath = frame_path(config, frame);
+        if encoder.is_none() && filepath.exists() {
+            println!(
+                "[{}] [ANIMATION] [{}/{}] Skipping existing {}",
+                now!(),
+                frame,
+                frames,
+                filepath.display()
+            );
+            continue;
+        }
+
+        let (matrix, how) = frame_at(frame)?;
+        match &mut encoder {
+            Some(encoder) => {
+                encoder.write_frame(&matrix)?;
+                println!(
+                    "[{}] [ANIMATION] [{}/{}] Done encoding frame {}",
+                    now!(),
+                    frame,
+                    frames,
+                    how
+                );
+            }
+            None => {
+                // Frames only get their final name once complete, so resuming never keeps a
+                // partial one
+                crate::write_png(&filepath, &matrix, &[])?;
+                println!(
+                    "[{}] [ANIMATION] [{}/{}] Done writing {} {}",
+                    now!(),
+                    frame,
+                    frames,
+                    filepath.display(),
+                    how
+                );
+            }
+        }
+    }
+
+    if let (Some(encoder), Some((partial, filepath))) = (encoder, encoded) {
+        encoder.finish()?;
+        fs::rename(&partial, &filepath)?;
+        println!("[{}] [ANIMATION] Wrote {}", now!(), filepath.display());
+    }
+    println!(
+        "[{}] [ANIMATION] Done in {:.1}s",
+        now!(),
+        began.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+fn render_frame(view: &View, config: &Config) -> Result<(Array2<Colour>, String)> {
+    let (matrix, precision) = crate::render_matrix(view, config, None, None)?;
+    Ok((matrix, format!("using {} precision", precision)))
+}
+
+fn frame_path(config: &Config, frame: usize) -> PathBuf {
+    config
+        .render
+        .directory
+        .join(format!("frame_{:05}.png", frame))
+}
+
+/// View at time t between 0 and 1 of a zoom towards the target
+///
+/// The scale is interpolated exponentially so the zoom speed looks constant. The offset from
+/// the target shrinks along with the scale, which keeps the target at the same place on the
+/// screen instead of letting it drift away early in the zoom, and reaches it exactly at the end.
+fn interpolate(start: &View, target: &(Fixed, Fixed), end_scale_factor: f64, t: f64) -> View {
+    let scale_factor = start.scale_factor * (end_scale_factor / start.scale_factor).powf(t);
+    let factor = if start.scale_factor != end_scale_factor {
+        (scale_factor - end_scale_factor) / (start.scale_factor - end_scale_factor)
+    } else {
+        1.0 - t
+    };
+
+    let bits = View::new(target.clone(), scale_factor, start.rotation).bits();
+    let factor = Fixed::from_f64(factor, bits);
+    let center = (
+        &target.0 + &(&(&start.center.0 - &target.0) * &factor),
+        &target.1 + &(&(&start.center.1 - &target.1) * &factor),
+    );
+    View::new(center, scale_factor, start.rotation)
+}
+
+/// Fills in the values missing from the keyframes, starting from the configuration
+fn resolve(animation: &KeyframeFile, config: &Config) -> Result<Vec<State>> {
+    if animation.keyframes.is_empty() {
+        return Err(Error::msg("there should be at least one keyframe"));
+    }
+    if animation
+        .keyframes
+        .windows(2)
+        .any(|pair| pair[0].time >= pair[1].time)
+    {
+        return Err(Error::msg("keyframes should be in increasing time order"));
+    }
+
+    let mut state = State {
+        time: 0.0,
+        center: View::initial(config).center,
+        zoom: config.view.zoom,
+        rotation: config.view.rotation,
+        max_iterations: config.max_iterations as f64,
+        julia_c: config.julia_c,
+        gradient_offset: config.gradient.offset,
+    };
+    let mut states = Vec::with_capacity(animation.keyframes.len());
+    for keyframe in &animation.keyframes {
+        state.time = keyframe.time;
+        if let Some([re, im]) = &keyframe.view.center {
+            state.center = (re.clone(), im.clone());
+        }
+        if let Some(zoom) = keyframe.view.zoom {
+            state.zoom = zoom;
+        }
+        if let Some(rotation) = keyframe.view.rotation {
+            state.rotation = rotation;
+        }
+        if let Some(max_iterations) = keyframe.max_iterations {
+            state.max_iterations = max_iterations as f64;
+        }
+        if let Some(julia_c) = keyframe.julia_c {
+            state.julia_c = julia_c;
+        }
+        if let Some(offset) = keyframe.gradient.offset {
+            state.gradient_offset = Some(offset);
+        }
+        // Views are made of fixed-point numbers, which have no infinity or NaN
+        if state.zoom <= 0.0
+            || !state.zoom.is_finite()
+            || !state.rotation.is_finite()
+            || !state.julia_c.iter().all(|v| v.is_finite())
+        {
+            return Err(Error::msg(format!(
+                "the keyframe at {}s should have a positive zoom, and a finite rotation and julia-c",
+                keyframe.time
+            )));
+        }
+        states.push(state.clone());
+    }
+    Ok(states)
+}
+
+/// Interpolates the keyframes at the given time
+fn state_at(states: &[State], animation: &KeyframeFile, time: f64) -> State {
+    let last = states.len() - 1;
+    let i = states[..last]
+        .iter()
+        .rposition(|s| s.time <= time)
+        .unwrap_or(0);
+    if i == last {
+        return states[last].clone();
+    }
+
+    // The neighbouring keyframes are repeated at the ends for Catmull-Rom
+    let p = [
+        &states[i.saturating_sub(1)],
+        &states[i],
+        &states[i + 1],
+        &states[(i + 2).min(last)],
+    ];
+    let u = ((time - p[1].time) / (p[2].time - p[1].time)).clamp(0.0, 1.0);
+    let w = weights(animation.interpolation, u);
+    let blend = |value: fn(&State) -> f64| (0..4).map(|k| w[k] * value(p[k])).sum::<f64>();
+
+    let zoom = if animation.log_zoom {
+        blend(|s| s.zoom.ln()).exp()
+    } else {
+        blend(|s| s.zoom)
+    };
+
+    // While zooming the centre follows the scale, so the point zoomed into keeps its place on
+    // the screen like in straight zooms
+    let center_weights = if p[1].zoom == p[2].zoom {
+        w
+    } else {
+        let v = (1.0 / zoom - 1.0 / p[1].zoom) / (1.0 / p[2].zoom - 1.0 / p[1].zoom);
+        match animation.interpolation {
+            Interpolation::CatmullRom => weights(Interpolation::CatmullRom, v),
+            _ => weights(Interpolation::Linear, v),
+        }
+    };
+    let bits = p.iter().map(|s| s.center.0.bits()).max().unwrap_or(0);
+    let blend_fixed = |value: fn(&State) -> &Fixed| {
+        (0..4).fold(Fixed::zero(bits), |sum, k| {
+            &sum + &(value(p[k]) * &Fixed::from_f64(center_weights[k], bits))
+        })
+    };
+
+    State {
+        time,
+        center: (blend_fixed(|s| &s.center.0), blend_fixed(|s| &s.center.1)),
+        zoom,
+        rotation: blend(|s| s.rotation),
+        max_iterations: blend(|s| s.max_iterations),
+        julia_c: [blend(|s| s.julia_c[0]), blend(|s| s.julia_c[1])],
+        // Gradients wrap around as soon as a neighbouring keyframe shifts them
+        gradient_offset: if p.iter().any(|s| s.gradient_offset.is_some()) {
+            Some(blend(|s| s.gradient_offset.unwrap_or(0.0)))
+        } else {
+            None
+        },
+    }
+}
+
+/// Weights of the keyframes before, at the start, at the end and after a segment
+fn weights(interpolation: Interpolation, u: f64) -> [f64; 4] {
+    match interpolation {
+        Interpolation::Linear => [0.0, 1.0 - u, u, 0.0],
+        Interpolation::Smoothstep => {
+            let u = u * u * (3.0 - 2.0 * u);
+            [0.0, 1.0 - u, u, 0.0]
+        }
+        Interpolation::CatmullRom => {
+            let (u2, u3) = (u * u, u * u * u);
+            [
+                (-u3 + 2.0 * u2 - u) / 2.0,
+                (3.0 * u3 - 5.0 * u2 + 2.0) / 2.0,
+                (-3.0 * u3 + 4.0 * u2 + u) / 2.0,
+                (u3 - u2) / 2.0,
+            ]
+        }
+    }
+}
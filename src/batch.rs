@@ -0,0 +1,172 @@
+use crate::{colour::Gradient, config::Config, fixed::Fixed, view::View};
+use anyhow::{Error, Result};
+use serde::Deserialize;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// List of images to render one after another
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct JobFile {
+    /// Number of jobs rendered at the same time
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    pub jobs: Vec<Job>,
+}
+
+/// A single image, anything missing comes from the configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Job {
+    pub output: PathBuf,
+    pub center: Option<[Fixed; 2]>,
+    /// Width of the view in the plane, the height follows from the resolution
+    pub size: Option<f64>,
+    pub max_iterations: Option<usize>,
+    pub gradient: Option<Gradient>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+}
+
+fn default_concurrency() -> usize {
+    1
+}
+
+/// Renders all the jobs, failing at the end if any of them did
+pub fn run(jobs: JobFile, config: &Config) -> Result<()> {
+    let total = jobs.jobs.len();
+    let concurrency = jobs.concurrency.max(1).min(total.max(1));
+    println!(
+        "[{}] [BATCH] Rendering {} jobs, {} at a time",
+        now!(),
+        total,
+        concurrency
+    );
+
+    let start = Instant::now();
+    let next = AtomicUsize::new(0);
+    let mut failures = thread::scope(|s| {
+        let workers: Vec<_> = (0..concurrency)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut failures = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::SeqCst);
+                        let job = match jobs.jobs.get(i) {
+                            Some(job) => job,
+                            None => break failures,
+                        };
+                        // A panicking job fails alone, the others keep rendering
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            render(job, config, i + 1, total)
+                        }))
+                        .unwrap_or_else(|panic| {
+                            Err(Error::msg(crate::jobs::panic_message(&*panic)))
+                        });
+                        if let Err(e) = result {
+                            eprintln!(
+                                "[{}] [BATCH] [{}/{}] [ERROR] {}: {}",
+                                now!(),
+                                i + 1,
+                                total,
+                                job.output.display(),
+                                e
+                            );
+                            failures.push((i, e));
+                        }
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().expect("batch worker panicked"))
+            .collect::<Vec<_>>()
+    });
+    failures.sort_by_key(|(i, _)| *i);
+
+    println!(
+        "[{}] [BATCH] Done in {}, {} succeeded, {} failed",
+        now!(),
+        seconds(start.elapsed()),
+        total - failures.len(),
+        failures.len()
+    );
+    for (i, e) in &failures {
+        println!(
+            "[{}] [BATCH]     {}: {}",
+            now!(),
+            jobs.jobs[*i].output.display(),
+            e
+        );
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::msg(format!(
+            "{} of {} jobs failed",
+            failures.len(),
+            total
+        )))
+    }
+}
+
+fn render(job: &Job, config: &Config, number: usize, total: usize) -> Result<()> {
+    if job
+        .size
+        .is_some_and(|size| size <= 0.0 || !size.is_finite())
+    {
+        return Err(Error::msg("the size should be a positive number"));
+    }
+    let mut config = config.clone();
+    if let Some(center) = &job.center {
+        config.view.center = Some(center.clone());
+    }
+    if let Some(max_iterations) = job.max_iterations {
+        config.max_iterations = max_iterations;
+    }
+    if let Some(gradient) = &job.gradient {
+        config.gradient = gradient.clone();
+    }
+    if let Some(width) = job.width {
+        config.render.resolution.width = width;
+    }
+    if let Some(height) = job.height {
+        config.render.resolution.height = height;
+    }
+
+    let mut view = crate::render_view(View::initial(&config), &config);
+    if let Some(size) = job.size {
+        view.fit_width(size, config.render.resolution);
+    }
+
+    println!(
+        "[{}] [BATCH] [{}/{}] Started rendering {}",
+        now!(),
+        number,
+        total,
+        job.output.display()
+    );
+    let start = Instant::now();
+    let (p, precision) = crate::render_inner(view, config, job.output.clone(), None, None)?;
+    println!(
+        "[{}] [BATCH] [{}/{}] Done rendering {} using {} precision in {}",
+        now!(),
+        number,
+        total,
+        p.display(),
+        precision,
+        seconds(start.elapsed())
+    );
+    Ok(())
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
@@ -0,0 +1,76 @@
+use crate::{
+    config::{Config, ViewConfig},
+    fixed::Fixed,
+    view::View,
+};
+use anyhow::Result;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct BookmarkFile {
+    #[serde(default)]
+    bookmarks: Vec<Bookmark>,
+}
+
+/// A view saved from the preview, with the same fields as the configured view
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Bookmark {
+    pub name: String,
+    pub center: [Fixed; 2],
+    #[serde(deserialize_with = "crate::config::positive")]
+    pub zoom: f64,
+    #[serde(default, deserialize_with = "crate::config::finite")]
+    pub rotation: f64,
+}
+
+impl Bookmark {
+    pub fn view(&self, config: &Config) -> View {
+        View::from_config(
+            config,
+            &ViewConfig {
+                center: Some(self.center.clone()),
+                zoom: self.zoom,
+                rotation: self.rotation,
+            },
+        )
+    }
+}
+
+/// Reads the bookmarks, of which there are none until the file exists
+pub fn read(path: &Path) -> Result<Vec<Bookmark>> {
+    if fs::metadata(path).is_err() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read(path)?;
+    let file: BookmarkFile = toml::from_slice(&contents)?;
+    Ok(file.bookmarks)
+}
+
+/// Appends a view of the preview to the bookmarks, named after the current time
+///
+/// The file is only ever appended to, so comments and names edited by hand are kept.
+pub fn save(path: &Path, view: &View, config: &Config) -> Result<Bookmark> {
+    let bookmark = Bookmark {
+        name: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        center: [view.center.0.clone(), view.center.1.clone()],
+        zoom: view.zoom_level(config),
+        rotation: view.rotation,
+    };
+    let entry = toml::to_string(&BookmarkFile {
+        bookmarks: vec![bookmark.clone()],
+    })?;
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() > 0 {
+        writeln!(file)?;
+    }
+    file.write_all(entry.as_bytes())?;
+    Ok(bookmark)
+}
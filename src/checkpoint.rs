@@ -0,0 +1,222 @@
+use crate::{colour::Colour, config::Resolution, renderer::Sample};
+use anyhow::{Error, Result};
+use ndarray::Array2;
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Start of every checkpoint, followed by the configuration, the size and the rows
+const MAGIC: &[u8] = b"fractal checkpoint 1\n";
+
+/// Samples of a render, only the complete rows of which are kept
+pub struct Rows {
+    pub samples: Array2<Sample>,
+    /// Whether each row has all of its samples
+    pub complete: Vec<bool>,
+}
+
+impl Rows {
+    /// Rows of a render which hasn't started
+    pub fn new(resolution: Resolution) -> Self {
+        Self {
+            samples: Array2::from_elem(
+                (resolution.width, resolution.height),
+                Sample {
+                    colour: Colour { r: 0, g: 0, b: 0 },
+                    height: None,
+                },
+            ),
+            complete: vec![false; resolution.height],
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.complete.iter().filter(|&&complete| complete).count()
+    }
+}
+
+/// Error of a render cancelled before it was done, holding the rows it completed
+pub struct Cancelled(pub Rows);
+
+impl fmt::Debug for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cancelled({} rows)", self.0.count())
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Where the checkpoint of an image is saved, the image is its path without the extension
+pub fn path(image: &Path) -> PathBuf {
+    let mut path = image.as_os_str().to_owned();
+    path.push(".checkpoint");
+    PathBuf::from(path)
+}
+
+/// Saves the complete rows of a render along with the configuration rendering it
+pub fn save(path: &Path, configuration: &str, rows: &Rows) -> Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    let (width, height) = rows.samples.dim();
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(configuration.len() as u64).to_le_bytes())?;
+    writer.write_all(configuration.as_bytes())?;
+    writer.write_all(&(width as u64).to_le_bytes())?;
+    writer.write_all(&(height as u64).to_le_bytes())?;
+    for y in 0..height {
+        writer.write_all(&[rows.complete[y] as u8])?;
+        if !rows.complete[y] {
+            continue;
+        }
+        for x in 0..width {
+            let Sample { colour, height } = rows.samples[[x, y]];
+            writer.write_all(&[colour.r, colour.g, colour.b, height.is_some() as u8])?;
+            writer.write_all(&height.unwrap_or(0.0).to_le_bytes())?;
+        }
+    }
+
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Reads back the configuration and the rows of a checkpoint
+pub fn load(path: &Path) -> Result<(String, Rows)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(Error::msg("not a checkpoint"));
+    }
+
+    let mut configuration = vec![0; read_u64(&mut reader)? as usize];
+    reader.read_exact(&mut configuration)?;
+    let configuration = String::from_utf8(configuration)?;
+    let resolution = Resolution {
+        width: read_u64(&mut reader)? as usize,
+        height: read_u64(&mut reader)? as usize,
+    };
+
+    let mut rows = Rows::new(resolution);
+    for y in 0..resolution.height {
+        let mut complete = [0];
+        reader.read_exact(&mut complete)?;
+        rows.complete[y] = complete[0] != 0;
+        if !rows.complete[y] {
+            continue;
+        }
+        for x in 0..resolution.width {
+            let mut pixel = [0; 12];
+            reader.read_exact(&mut pixel)?;
+            let mut height = [0; 8];
+            height.copy_from_slice(&pixel[4..]);
+            rows.samples[[x, y]] = Sample {
+                colour: Colour {
+                    r: pixel[0],
+                    g: pixel[1],
+                    b: pixel[2],
+                },
+                height: Some(f64::from_le_bytes(height)).filter(|_| pixel[3] != 0),
+            };
+        }
+    }
+    Ok((configuration, rows))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, jobs::Progress, renderer::Renderer, view::View};
+
+    fn rgb(sample: Sample) -> (u8, u8, u8) {
+        (sample.colour.r, sample.colour.g, sample.colour.b)
+    }
+
+    #[test]
+    fn rows_read_back_as_saved() {
+        let mut rows = Rows::new(Resolution {
+            width: 3,
+            height: 2,
+        });
+        rows.complete[1] = true;
+        for x in 0..3 {
+            rows.samples[[x, 1]] = Sample {
+                colour: Colour {
+                    r: x as u8,
+                    g: 10,
+                    b: 20,
+                },
+                height: Some(x as f64 * 0.5).filter(|_| x != 1),
+            };
+        }
+
+        let path = std::env::temp_dir().join(format!("fractal-{}.checkpoint", std::process::id()));
+        save(&path, "max-iterations = 10\n", &rows).unwrap();
+        let (configuration, read) = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(configuration, "max-iterations = 10\n");
+        assert_eq!(read.complete, [false, true]);
+        for x in 0..3 {
+            let (saved, read) = (rows.samples[[x, 1]], read.samples[[x, 1]]);
+            assert_eq!(rgb(saved), rgb(read));
+            assert_eq!(saved.height, read.height);
+        }
+    }
+
+    #[test]
+    fn cancelled_renders_resume_from_their_rows() {
+        let config = Config::default();
+        let resolution = Resolution {
+            width: 4,
+            height: 3,
+        };
+        let renderer = Renderer::new(&View::initial(&config), resolution, &config);
+        let full = crate::sample_all(&renderer, resolution, None, None).unwrap();
+
+        // Rows kept from an earlier render aren't sampled again, even once cancelled
+        let mut rows = Rows::new(resolution);
+        rows.complete[1] = true;
+        for x in 0..resolution.width {
+            rows.samples[[x, 1]].colour = Colour { r: 1, g: 2, b: 3 };
+        }
+        let progress = Progress::default();
+        progress.cancel();
+        let error = crate::sample_all(&renderer, resolution, Some(&progress), Some(rows));
+        let Cancelled(rows) = error.unwrap_err().downcast().unwrap();
+        assert_eq!(rows.complete, [false, true, false]);
+
+        let resumed = crate::sample_all(&renderer, resolution, None, Some(rows)).unwrap();
+        for ((x, y), &sample) in resumed.indexed_iter() {
+            if y == 1 {
+                assert_eq!(rgb(sample), (1, 2, 3));
+            } else {
+                assert_eq!(rgb(sample), rgb(full[[x, y]]));
+            }
+        }
+    }
+}
@@ -1,9 +1,12 @@
 use palette::{FromColor, IntoColor, LinSrgb};
-use serde::de::{Deserialize, Deserializer, Error as _, MapAccess, Visitor};
+use serde::{
+    de::{Deserialize, Deserializer, Error as _, MapAccess, Visitor},
+    ser::{Serialize, SerializeStruct, Serializer},
+};
 use std::{convert::TryFrom, fmt, num::NonZeroUsize};
 
 #[derive(Debug, Copy, Clone, serde::Deserialize)]
-#[serde(try_from = "&str")]
+#[serde(try_from = "String")]
 pub struct Colour {
     pub r: u8,
     pub g: u8,
@@ -11,21 +14,72 @@ pub struct Colour {
 }
 
 #[derive(Debug, Clone)]
-pub enum Gradient {
+pub struct Gradient {
+    colours: Colours,
+    /// What the gradient was built from, kept so it can be written back
+    mode: Mode,
+    base_colours: Vec<Colour>,
+    cycles: usize,
+    /// Shift of the gradient, once set values pushed past either end wrap around to the other
+    /// one, even with a shift of 0 so animations going through it don't flicker
+    pub offset: Option<f64>,
+}
+
+#[derive(Debug, Copy, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+enum Mode {
+    Rgb,
+    Hsv,
+}
+
+#[derive(Debug, Clone)]
+enum Colours {
     Rgb(palette::Gradient<palette::LinSrgb<f64>>),
     Hsv(palette::Gradient<palette::Hsv<palette::encoding::srgb::Srgb, f64>>),
 }
 
 impl Gradient {
     pub fn get(&self, i: f64) -> Colour {
-        let c = match &self {
-            Gradient::Rgb(g) => g.get(i),
-            Gradient::Hsv(g) => g.get(i).into_rgb(),
+        // Without an offset values past the ends keep clamping to the end colours
+        let i = match self.offset {
+            Some(offset) => (i + offset).rem_euclid(1.0),
+            None => i,
+        };
+        let c = match &self.colours {
+            Colours::Rgb(g) => g.get(i),
+            Colours::Hsv(g) => g.get(i).into_rgb(),
         };
         Colour::from(c)
     }
 }
 
+impl Colour {
+    /// Linear interpolation towards other, with factor between 0 and 1
+    pub fn mix(self, other: Colour, factor: f64) -> Colour {
+        let lerp = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * factor).round() as u8;
+        Colour {
+            r: lerp(self.r, other.r),
+            g: lerp(self.g, other.g),
+            b: lerp(self.b, other.b),
+        }
+    }
+}
+
+impl fmt::Display for Colour {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl Serialize for Colour {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
 impl From<LinSrgb<f64>> for Colour {
     fn from(c: LinSrgb<f64>) -> Self {
         Colour {
@@ -42,10 +96,10 @@ impl From<Colour> for LinSrgb<f64> {
     }
 }
 
-impl TryFrom<&str> for Colour {
+impl TryFrom<String> for Colour {
     type Error = anyhow::Error;
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
+    fn try_from(value: String) -> Result<Self, Self::Error> {
         if !value.starts_with('#') || value.len() != 7 {
             return Err(anyhow::anyhow!("colours should be in #rrggbb format"));
         }
@@ -60,29 +114,37 @@ impl TryFrom<&str> for Colour {
 
 impl Default for Gradient {
     fn default() -> Self {
-        Self::Hsv(palette::Gradient::new(
-            [
-                Colour {
-                    r: 0xdd,
-                    g: 0x22,
-                    b: 0x22,
-                },
-                Colour {
-                    r: 0x22,
-                    g: 0xdd,
-                    b: 0x22,
-                },
-                Colour {
-                    r: 0x22,
-                    g: 0x22,
-                    b: 0xdd,
-                },
-            ]
-            .iter()
-            .copied()
-            .map(<LinSrgb<f64>>::from)
-            .map(FromColor::from_rgb),
-        ))
+        let base_colours = vec![
+            Colour {
+                r: 0xdd,
+                g: 0x22,
+                b: 0x22,
+            },
+            Colour {
+                r: 0x22,
+                g: 0xdd,
+                b: 0x22,
+            },
+            Colour {
+                r: 0x22,
+                g: 0x22,
+                b: 0xdd,
+            },
+        ];
+        let colours = Colours::Hsv(palette::Gradient::new(
+            base_colours
+                .iter()
+                .copied()
+                .map(<LinSrgb<f64>>::from)
+                .map(FromColor::from_rgb),
+        ));
+        Self {
+            colours,
+            mode: Mode::Hsv,
+            base_colours,
+            cycles: 1,
+            offset: None,
+        }
     }
 }
 
@@ -97,13 +159,7 @@ impl<'de> Deserialize<'de> for Gradient {
             Mode,
             Colours,
             Cycles,
-        }
-
-        #[derive(serde::Deserialize)]
-        #[serde(rename_all = "UPPERCASE")]
-        enum Mode {
-            Rgb,
-            Hsv,
+            Offset,
         }
 
         struct GradientVisitor;
@@ -122,6 +178,7 @@ impl<'de> Deserialize<'de> for Gradient {
                 let mut mode = None;
                 let mut colours: Option<Vec<Colour>> = None;
                 let mut cycles: Option<NonZeroUsize> = None;
+                let mut offset = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -147,6 +204,14 @@ impl<'de> Deserialize<'de> for Gradient {
                             }
                             cycles = Some(map.next_value()?);
                         }
+                        Field::Offset => {
+                            if offset.is_some() {
+                                return Err(<A as MapAccess<'de>>::Error::duplicate_field(
+                                    "offset",
+                                ));
+                            }
+                            offset = Some(map.next_value()?);
+                        }
                     }
                 }
 
@@ -161,16 +226,64 @@ impl<'de> Deserialize<'de> for Gradient {
                 }
                 let lin_srgb = colours.into_iter().map(<LinSrgb<f64>>::from);
 
-                match mode {
-                    Mode::Rgb => Ok(Gradient::Rgb(palette::Gradient::new(lin_srgb))),
-                    Mode::Hsv => Ok(Gradient::Hsv(palette::Gradient::new(
-                        lin_srgb.map(FromColor::from_rgb),
-                    ))),
-                }
+                let colours = match mode {
+                    Mode::Rgb => Colours::Rgb(palette::Gradient::new(lin_srgb)),
+                    Mode::Hsv => {
+                        Colours::Hsv(palette::Gradient::new(lin_srgb.map(FromColor::from_rgb)))
+                    }
+                };
+                Ok(Gradient {
+                    colours,
+                    mode,
+                    base_colours,
+                    cycles,
+                    offset,
+                })
             }
         }
 
-        const FIELDS: &[&str] = &["mode", "colours", "cycles"];
+        const FIELDS: &[&str] = &["mode", "colours", "cycles", "offset"];
         deserializer.deserialize_struct("Gradient", FIELDS, GradientVisitor)
     }
 }
+
+impl Serialize for Gradient {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("Gradient", 4)?;
+        state.serialize_field("mode", &self.mode)?;
+        state.serialize_field("colours", &self.base_colours)?;
+        state.serialize_field("cycles", &self.cycles)?;
+        match self.offset {
+            Some(offset) => state.serialize_field("offset", &offset)?,
+            None => state.skip_field("offset")?,
+        }
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_wrap_even_at_zero() {
+        let mut gradient = Gradient::default();
+        assert_eq!(
+            gradient.get(1.25).to_string(),
+            gradient.get(1.0).to_string()
+        );
+
+        gradient.offset = Some(0.0);
+        assert_eq!(
+            gradient.get(1.25).to_string(),
+            gradient.get(0.25).to_string()
+        );
+        assert_eq!(
+            gradient.get(-0.25).to_string(),
+            gradient.get(0.75).to_string()
+        );
+    }
+}
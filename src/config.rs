@@ -1,37 +1,271 @@
-use crate::colour::{Colour, Gradient};
-use anyhow::Result;
-use sdl2::keyboard::Keycode;
-use serde::Deserialize;
+use crate::{
+    colour::{Colour, Gradient},
+    expr::Expr,
+    fixed::Fixed,
+    formula::{BurningShip, EscapeTimeFractal, Mandelbrot, Multibrot, Tricorn},
+};
+use anyhow::{Error, Result};
+use sdl2::{
+    keyboard::{Keycode, Mod},
+    mouse::MouseButton,
+};
+use serde::{
+    de::{self, DeserializeOwned},
+    Deserialize, Deserializer,
+};
 use std::{
     convert::TryFrom,
+    ffi::OsStr,
     fs,
     path::{Path, PathBuf},
 };
+use toml::{value::Table, Value};
 
 pub fn read(path: impl AsRef<Path>) -> Result<Config> {
     let contents = fs::read(path)?;
-    let config = toml::from_slice(&contents)?;
+    from_table(toml::from_slice(&contents)?)
+}
+
+/// Builds the configuration from a TOML table, keeping the table around
+pub fn from_table(source: Table) -> Result<Config> {
+    let mut config: Config = Value::Table(source.clone()).try_into()?;
+    config.source = source;
+    if config.colouring.mode == ColouringMode::Distance && !config.escape_time().holomorphic() {
+        return Err(Error::msg(
+            "distance colouring needs a holomorphic formula, which burning-ship, tricorn and \
+             expressions using conj, abs, re or im aren't",
+        ));
+    }
     Ok(config)
 }
 
+/// Smooth colouring divides by ln |n| and negative exponents blow up at 0, so |n| must be over 1
+fn multibrot_exponent<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let exponent = f64::deserialize(deserializer)?;
+    if exponent.abs() > 1.0 && exponent.is_finite() {
+        Ok(exponent)
+    } else {
+        Err(de::Error::custom(format!(
+            "multibrot-exponent must be greater than 1 or less than -1, got {}",
+            exponent
+        )))
+    }
+}
+
+/// Zooms divide the size of the view, so they must be positive and finite
+pub fn positive<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let value = f64::deserialize(deserializer)?;
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err(de::Error::custom(format!(
+            "expected a positive number, got {}",
+            value
+        )))
+    }
+}
+
+/// Values which end up in fixed-point coordinates, which have no infinity or NaN
+pub fn finite<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let value = f64::deserialize(deserializer)?;
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(de::Error::custom(format!(
+            "expected a finite number, got {}",
+            value
+        )))
+    }
+}
+
+fn finite_point<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[f64; 2], D::Error> {
+    let point = <[f64; 2]>::deserialize(deserializer)?;
+    if point.iter().all(|v| v.is_finite()) {
+        Ok(point)
+    } else {
+        Err(de::Error::custom(format!(
+            "expected finite numbers, got {:?}",
+            point
+        )))
+    }
+}
+
+/// Reads an input file of a command, as JSON if it has a .json extension and TOML otherwise
+pub fn read_data<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T> {
+    let path = path.as_ref();
+    let contents = fs::read(path)?;
+    let data = if path.extension() == Some(OsStr::new("json")) {
+        serde_json::from_slice(&contents)?
+    } else {
+        toml::from_slice(&contents)?
+    };
+    Ok(data)
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct Config {
+    pub fractal: Fractal,
+    #[serde(deserialize_with = "finite_point")]
+    pub julia_c: [f64; 2],
+    pub formula: Formula,
+    #[serde(deserialize_with = "multibrot_exponent")]
+    pub multibrot_exponent: f64,
     pub max_iterations: usize,
+    pub view: ViewConfig,
+    pub deep_zoom: DeepZoomConfig,
+    pub optimisations: OptimisationsConfig,
     pub preview: PreviewConfig,
     pub render: RenderConfig,
+    pub animation: AnimationConfig,
+    pub exponential_map: ExponentialMapConfig,
     pub gradient: Gradient,
+    pub colouring: ColouringConfig,
+    pub orbit_trap: OrbitTrapConfig,
+    pub lighting: LightingConfig,
+    pub interior: InteriorConfig,
     pub black: Colour,
+    /// Table the configuration was read from, written back into rendered images
+    #[serde(skip)]
+    pub source: Table,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fractal {
+    Mandelbrot,
+    Julia,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Formula {
+    Mandelbrot,
+    BurningShip,
+    Tricorn,
+    Multibrot,
+    Expression(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ViewConfig {
+    pub center: Option<[Fixed; 2]>,
+    #[serde(deserialize_with = "positive")]
+    pub zoom: f64,
+    /// Counterclockwise rotation in degrees
+    #[serde(deserialize_with = "finite")]
+    pub rotation: f64,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct DeepZoomConfig {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct OptimisationsConfig {
+    pub bulb_rejection: bool,
+    pub periodicity_checking: bool,
 }
 
 #[derive(Debug, Copy, Clone, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
+pub struct ColouringConfig {
+    pub mode: ColouringMode,
+    /// Width of the lines drawn along the boundary in distance mode, in pixels
+    pub thickness: f64,
+    pub line: Colour,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColouringMode {
+    Smooth,
+    Distance,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct OrbitTrapConfig {
+    pub shape: TrapShape,
+    pub center: [f64; 2],
+    /// Radius of the circle trap
+    pub radius: f64,
+    /// Rotation of the line and cross traps in degrees
+    pub angle: f64,
+    /// Width of the Pickover stalks, points further away aren't caught
+    pub width: f64,
+    /// Distance mapped to the end of the gradient
+    pub scale: f64,
+    /// Weight of the trap colour blended over the smooth colouring
+    pub blend: f64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrapShape {
+    None,
+    Point,
+    Line,
+    Cross,
+    Circle,
+    Stalk,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct InteriorConfig {
+    pub mode: InteriorMode,
+    /// Gradients used by each mode, falling back to the main gradient
+    pub magnitude: Option<Gradient>,
+    pub period: Option<Gradient>,
+    pub distance: Option<Gradient>,
+    pub atom_domain: Option<Gradient>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InteriorMode {
+    Flat,
+    Magnitude,
+    Period,
+    Distance,
+    AtomDomain,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct LightingConfig {
+    pub enabled: bool,
+    /// Direction of the light in degrees, counterclockwise from the right of the image
+    pub azimuth: f64,
+    /// Angle of the light above the image in degrees
+    pub elevation: f64,
+    pub ambient: f64,
+    pub diffuse: f64,
+    pub specular: f64,
+    pub shininess: f64,
+    /// Scale of the height map, higher values give steeper slopes
+    pub strength: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
 pub struct PreviewConfig {
     #[serde(flatten)]
     pub resolution: Resolution,
     pub move_factor: f64,
+    #[serde(deserialize_with = "positive")]
     pub zoom_factor: f64,
+    /// File the bookmark key appends views to
+    pub bookmarks: PathBuf,
+    /// Number of navigation steps which can be undone
+    pub history: usize,
     pub keys: PreviewKeysConfig,
+    pub mouse: PreviewMouseConfig,
 }
 
 #[derive(Debug, Copy, Clone, Deserialize)]
@@ -44,6 +278,27 @@ pub struct PreviewKeysConfig {
     pub zoom_in: Key,
     pub zoom_out: Key,
     pub render: Key,
+    /// Cancels the last queued render
+    pub cancel_render: Key,
+    pub undo: Key,
+    pub redo: Key,
+    pub bookmark: Key,
+    pub next_bookmark: Key,
+    pub previous_bookmark: Key,
+    pub hud: Key,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PreviewMouseConfig {
+    /// Dragging moves the view along
+    pub pan: MouseBinding,
+    /// Dragging draws a rectangle to zoom into
+    pub box_zoom: MouseBinding,
+    /// Scrolling zooms around the cursor
+    pub zoom: MouseBinding,
+    /// Clicking prints the coordinates of the cursor
+    pub coordinates: MouseBinding,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -52,6 +307,65 @@ pub struct RenderConfig {
     #[serde(flatten)]
     pub resolution: Resolution,
     pub directory: PathBuf,
+    /// Number of renders from the preview run at once, the others wait in line
+    pub concurrency: usize,
+    /// What happens to unfinished renders when the preview is closed
+    pub on_quit: QuitPolicy,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuitPolicy {
+    /// Asks in the terminal which of the other policies to follow
+    Ask,
+    Wait,
+    Cancel,
+    /// Cancels the renders but saves the rows they completed, to resume them later
+    Checkpoint,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct AnimationConfig {
+    pub frames: usize,
+    /// Centre of the last frame, defaults to the centre of the view
+    pub center: Option<[Fixed; 2]>,
+    /// Zoom of the last frame relative to the default view
+    #[serde(deserialize_with = "positive")]
+    pub zoom: f64,
+    pub format: AnimationFormat,
+    pub fps: f64,
+    /// Number of times the animation plays, 0 loops forever
+    pub loops: u16,
+    /// Dithering used when reducing GIF frames to 256 colours
+    pub dithering: Dithering,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnimationFormat {
+    /// Numbered PNG frames in the render directory
+    Png,
+    Apng,
+    Gif,
+    Y4m,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dithering {
+    None,
+    FloydSteinberg,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ExponentialMapConfig {
+    /// Samples around the centre, which is the width of the strip, defaults to the length of the
+    /// circle through the corners of the first frame so it gets a sample per pixel
+    pub width: Option<usize>,
+    /// Number of times the radius halves from the top to the bottom of the strip
+    pub octaves: f64,
 }
 
 #[derive(Debug, Copy, Clone, Deserialize)]
@@ -62,21 +376,146 @@ pub struct Resolution {
 }
 
 #[derive(Debug, Copy, Clone, Deserialize)]
-#[serde(try_from = "&str")]
+#[serde(try_from = "String")]
 pub struct Key(Keycode);
 
+/// Mouse button or wheel, along with the exact modifier keys which have to be held
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MouseBinding {
+    input: MouseInput,
+    shift: bool,
+    ctrl: bool,
+    alt: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum MouseInput {
+    Button(MouseButton),
+    Wheel,
+    Disabled,
+}
+
+impl Config {
+    pub fn escape_time(&self) -> Box<dyn EscapeTimeFractal> {
+        match &self.formula {
+            Formula::Mandelbrot => Box::new(Mandelbrot),
+            Formula::BurningShip => Box::new(BurningShip),
+            Formula::Tricorn => Box::new(Tricorn),
+            Formula::Multibrot => Box::new(Multibrot {
+                exponent: self.multibrot_exponent,
+            }),
+            Formula::Expression(expr) => Box::new(expr.clone()),
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
+            fractal: Fractal::Mandelbrot,
+            julia_c: [-0.8, 0.156],
+            formula: Formula::Mandelbrot,
+            multibrot_exponent: 3.0,
             max_iterations: 512,
+            view: Default::default(),
+            deep_zoom: Default::default(),
+            optimisations: Default::default(),
             preview: Default::default(),
             render: Default::default(),
+            animation: Default::default(),
+            exponential_map: Default::default(),
             gradient: Default::default(),
+            colouring: Default::default(),
+            orbit_trap: Default::default(),
+            lighting: Default::default(),
+            interior: Default::default(),
             black: Colour {
                 r: 0x00,
                 g: 0x00,
                 b: 0x00,
             },
+            source: Default::default(),
+        }
+    }
+}
+
+impl Default for ViewConfig {
+    fn default() -> Self {
+        Self {
+            center: None,
+            zoom: 1.0,
+            rotation: 0.0,
+        }
+    }
+}
+
+impl Default for DeepZoomConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+impl Default for OptimisationsConfig {
+    fn default() -> Self {
+        Self {
+            bulb_rejection: true,
+            periodicity_checking: true,
+        }
+    }
+}
+
+impl Default for ColouringConfig {
+    fn default() -> Self {
+        Self {
+            mode: ColouringMode::Smooth,
+            thickness: 1.0,
+            line: Colour {
+                r: 0x00,
+                g: 0x00,
+                b: 0x00,
+            },
+        }
+    }
+}
+
+impl Default for OrbitTrapConfig {
+    fn default() -> Self {
+        Self {
+            shape: TrapShape::None,
+            center: [0.0, 0.0],
+            radius: 1.0,
+            angle: 0.0,
+            width: 0.1,
+            scale: 1.0,
+            blend: 1.0,
+        }
+    }
+}
+
+impl Default for LightingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            azimuth: 135.0,
+            elevation: 45.0,
+            ambient: 0.4,
+            diffuse: 0.6,
+            specular: 0.3,
+            shininess: 20.0,
+            strength: 1.0,
+        }
+    }
+}
+
+impl Default for InteriorConfig {
+    fn default() -> Self {
+        Self {
+            mode: InteriorMode::Flat,
+            magnitude: None,
+            period: None,
+            distance: None,
+            atom_domain: None,
         }
     }
 }
@@ -90,7 +529,10 @@ impl Default for PreviewConfig {
             },
             move_factor: 0.125,
             zoom_factor: 1.25,
+            bookmarks: PathBuf::from("bookmarks.toml"),
+            history: 100,
             keys: Default::default(),
+            mouse: Default::default(),
         }
     }
 }
@@ -105,6 +547,27 @@ impl Default for PreviewKeysConfig {
             zoom_in: Key(Keycode::Up),
             zoom_out: Key(Keycode::Down),
             render: Key(Keycode::R),
+            cancel_render: Key(Keycode::C),
+            undo: Key(Keycode::Z),
+            redo: Key(Keycode::Y),
+            bookmark: Key(Keycode::B),
+            next_bookmark: Key(Keycode::N),
+            previous_bookmark: Key(Keycode::P),
+            hud: Key(Keycode::H),
+        }
+    }
+}
+
+impl Default for PreviewMouseConfig {
+    fn default() -> Self {
+        Self {
+            pan: MouseBinding::new(MouseInput::Button(MouseButton::Left)),
+            box_zoom: MouseBinding {
+                shift: true,
+                ..MouseBinding::new(MouseInput::Button(MouseButton::Left))
+            },
+            zoom: MouseBinding::new(MouseInput::Wheel),
+            coordinates: MouseBinding::new(MouseInput::Button(MouseButton::Left)),
         }
     }
 }
@@ -117,21 +580,201 @@ impl Default for RenderConfig {
                 height: 2160,
             },
             directory: PathBuf::from("renders"),
+            concurrency: 1,
+            on_quit: QuitPolicy::Ask,
+        }
+    }
+}
+
+impl Default for AnimationConfig {
+    fn default() -> Self {
+        Self {
+            frames: 100,
+            center: None,
+            zoom: 1000.0,
+            format: AnimationFormat::Png,
+            fps: 30.0,
+            loops: 0,
+            dithering: Dithering::FloydSteinberg,
+        }
+    }
+}
+
+impl Default for ExponentialMapConfig {
+    fn default() -> Self {
+        Self {
+            width: None,
+            octaves: 20.0,
         }
     }
 }
 
-impl TryFrom<&str> for Key {
+impl TryFrom<String> for Key {
     type Error = &'static str;
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let keycode = Keycode::from_name(value).ok_or("unknown SDL2 keycode")?;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let keycode = Keycode::from_name(&value).ok_or("unknown SDL2 keycode")?;
         Ok(Self(keycode))
     }
 }
 
+impl MouseBinding {
+    fn new(input: MouseInput) -> Self {
+        Self {
+            input,
+            shift: false,
+            ctrl: false,
+            alt: false,
+        }
+    }
+
+    pub fn button(&self, button: MouseButton, keymod: Mod) -> bool {
+        self.input == MouseInput::Button(button) && self.modifiers(keymod)
+    }
+
+    pub fn wheel(&self, keymod: Mod) -> bool {
+        self.input == MouseInput::Wheel && self.modifiers(keymod)
+    }
+
+    fn modifiers(&self, keymod: Mod) -> bool {
+        self.shift == keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD)
+            && self.ctrl == keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD)
+            && self.alt == keymod.intersects(Mod::LALTMOD | Mod::RALTMOD)
+    }
+}
+
+impl TryFrom<String> for MouseBinding {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mut parts = value.split('+').map(str::trim).rev();
+        let input = match parts
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "left" => MouseInput::Button(MouseButton::Left),
+            "middle" => MouseInput::Button(MouseButton::Middle),
+            "right" => MouseInput::Button(MouseButton::Right),
+            "x1" => MouseInput::Button(MouseButton::X1),
+            "x2" => MouseInput::Button(MouseButton::X2),
+            "wheel" => MouseInput::Wheel,
+            "none" => MouseInput::Disabled,
+            _ => return Err("unknown mouse button, expected Left, Middle, Right, X1, X2 or Wheel"),
+        };
+
+        let mut binding = Self::new(input);
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "shift" => binding.shift = true,
+                "ctrl" => binding.ctrl = true,
+                "alt" => binding.alt = true,
+                _ => return Err("unknown modifier, expected Shift, Ctrl or Alt"),
+            }
+        }
+        Ok(binding)
+    }
+}
+
+impl TryFrom<String> for Formula {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "mandelbrot" => Ok(Self::Mandelbrot),
+            "burning-ship" => Ok(Self::BurningShip),
+            "tricorn" => Ok(Self::Tricorn),
+            "multibrot" => Ok(Self::Multibrot),
+            _ => Ok(Self::Expression(Expr::parse(&value)?)),
+        }
+    }
+}
+
 impl PartialEq<Keycode> for Key {
     fn eq(&self, other: &Keycode) -> bool {
         self.0.eq(other)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_exponent(exponent: f64) -> Result<Config> {
+        let mut source = Table::new();
+        source.insert("multibrot-exponent".to_owned(), Value::Float(exponent));
+        from_table(source)
+    }
+
+    #[test]
+    fn tables_read_values_parsed_from_strings() {
+        let source: Table = toml::from_str(
+            r##"
+            formula = "z^2 + c"
+            black = "#102030"
+            [view]
+            center = ["-0.75", "0.1"]
+            [preview]
+            width = 800
+            height = 450
+            [preview.keys]
+            render = "F"
+            [preview.mouse]
+            pan = "Ctrl+Right"
+            "##,
+        )
+        .unwrap();
+        let config = from_table(source).unwrap();
+        assert!(matches!(config.formula, Formula::Expression(_)));
+        assert_eq!(config.black.to_string(), "#102030");
+        assert_eq!(config.view.center.unwrap()[0].to_string(), "-0.75");
+    }
+
+    #[test]
+    fn multibrot_exponent_is_over_one_in_magnitude() {
+        assert!(with_exponent(3.0).is_ok());
+        assert!(with_exponent(-2.0).is_ok());
+        for exponent in [1.0, -1.0, 0.5, 0.0, f64::INFINITY] {
+            assert!(
+                with_exponent(exponent).is_err(),
+                "{} was accepted",
+                exponent
+            );
+        }
+    }
+
+    #[test]
+    fn views_need_finite_values() {
+        let read = |source: &str| from_table(toml::from_str(source).unwrap());
+        assert!(read("julia-c = [0.3, -0.5]\n[view]\nzoom = 1e30\nrotation = -45.0").is_ok());
+        for source in [
+            "[view]\nzoom = 0.0",
+            "[view]\nzoom = -2.0",
+            "[view]\nzoom = inf",
+            "[view]\nrotation = nan",
+            "julia-c = [0.3, inf]",
+            "[animation]\nzoom = 0.0",
+        ] {
+            assert!(read(source).is_err(), "{:?} was accepted", source);
+        }
+    }
+
+    #[test]
+    fn distance_colouring_needs_a_holomorphic_formula() {
+        let with_formula = |formula: &str| {
+            let source: Table = toml::from_str(&format!(
+                "formula = \"{}\"\n[colouring]\nmode = \"distance\"",
+                formula
+            ))
+            .unwrap();
+            from_table(source)
+        };
+        for formula in ["mandelbrot", "multibrot", "z^3 + sin(c)"] {
+            assert!(with_formula(formula).is_ok(), "{} was refused", formula);
+        }
+        for formula in ["burning-ship", "tricorn", "conj(z)^2 + c", "abs(z)^2 + c"] {
+            assert!(with_formula(formula).is_err(), "{} was accepted", formula);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use crate::fixed::Fixed;
+use std::ops::{Add, Mul, Sub};
+
+/// Unevaluated sum of two f64, giving about 106 bits of mantissa
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DoubleDouble {
+    pub hi: f64,
+    pub lo: f64,
+}
+
+impl DoubleDouble {
+    pub fn from_fixed(value: &Fixed) -> Self {
+        let hi = value.to_f64();
+        let lo = (value - &Fixed::from_f64(hi, value.bits())).to_f64();
+        Self { hi, lo }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.hi + self.lo
+    }
+
+    pub fn square(self) -> Self {
+        let (p, e) = two_prod(self.hi, self.hi);
+        let e = e + 2.0 * self.hi * self.lo;
+        quick_two_sum(p, e)
+    }
+}
+
+impl From<f64> for DoubleDouble {
+    fn from(value: f64) -> Self {
+        Self { hi: value, lo: 0.0 }
+    }
+}
+
+impl Add for DoubleDouble {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        let (s, e) = two_sum(self.hi, rhs.hi);
+        let (t, f) = two_sum(self.lo, rhs.lo);
+        let s = quick_two_sum(s, e + t);
+        quick_two_sum(s.hi, s.lo + f)
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self + Self {
+            hi: -rhs.hi,
+            lo: -rhs.lo,
+        }
+    }
+}
+
+impl Mul for DoubleDouble {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let (p, e) = two_prod(self.hi, rhs.hi);
+        let e = e + (self.hi * rhs.lo + self.lo * rhs.hi);
+        quick_two_sum(p, e)
+    }
+}
+
+/// Sum of a and b with its rounding error, for |a| >= |b|
+fn quick_two_sum(a: f64, b: f64) -> DoubleDouble {
+    let hi = a + b;
+    let lo = b - (hi - a);
+    DoubleDouble { hi, lo }
+}
+
+/// Sum of a and b with its rounding error
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    let bb = s - a;
+    let e = (a - (s - bb)) + (b - bb);
+    (s, e)
+}
+
+/// Product of a and b with its rounding error, using Dekker's splitting so it doesn't rely on
+/// a hardware fused multiply-add
+fn two_prod(a: f64, b: f64) -> (f64, f64) {
+    let p = a * b;
+    let (a_hi, a_lo) = split(a);
+    let (b_hi, b_lo) = split(b);
+    let e = ((a_hi * b_hi - p) + a_hi * b_lo + a_lo * b_hi) + a_lo * b_lo;
+    (p, e)
+}
+
+fn split(a: f64) -> (f64, f64) {
+    const SPLITTER: f64 = 134_217_729.0; // 2^27 + 1
+    let t = SPLITTER * a;
+    let hi = t - (t - a);
+    (hi, a - hi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(value: DoubleDouble, bits: usize) -> Fixed {
+        &Fixed::from_f64(value.hi, bits) + &Fixed::from_f64(value.lo, bits)
+    }
+
+    fn assert_close(value: DoubleDouble, expected: &Fixed) {
+        let error = (&fixed(value, expected.bits()) - expected).to_f64().abs();
+        assert!(
+            error < 1e-30,
+            "{:?} is {:e} away from {}",
+            value,
+            error,
+            expected
+        );
+    }
+
+    fn operands() -> (Fixed, Fixed) {
+        (
+            Fixed::parse("0.1234567890123456789012345678901234").unwrap(),
+            Fixed::parse("-1.9876543210987654321098765432109876").unwrap(),
+        )
+    }
+
+    #[test]
+    fn from_fixed_keeps_the_digits_f64_drops() {
+        let (a, b) = operands();
+        assert_close(DoubleDouble::from_fixed(&a), &a);
+        assert_close(DoubleDouble::from_fixed(&b), &b);
+    }
+
+    #[test]
+    fn add_and_sub() {
+        let (a, b) = operands();
+        let (x, y) = (DoubleDouble::from_fixed(&a), DoubleDouble::from_fixed(&b));
+        assert_close(x + y, &(&a + &b));
+        assert_close(x - y, &(&a - &b));
+    }
+
+    #[test]
+    fn mul_and_square() {
+        let (a, b) = operands();
+        let (x, y) = (DoubleDouble::from_fixed(&a), DoubleDouble::from_fixed(&b));
+        assert_close(x * y, &(&a * &b));
+        assert_close(y.square(), &(&b * &b));
+    }
+}
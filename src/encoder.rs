@@ -0,0 +1,208 @@
+use crate::{
+    colour::Colour,
+    config::{AnimationConfig, AnimationFormat, Dithering, Resolution},
+};
+use anyhow::{Error, Result};
+use color_quant::NeuQuant;
+use ndarray::Array2;
+use png::{BitDepth, ColorType};
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// Writes the frames of an animation to a single file as they are rendered
+pub enum AnimationEncoder {
+    Apng(png::Writer<BufWriter<File>>),
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        /// Delay between frames in hundredths of a second
+        delay: u16,
+        dithering: Dithering,
+    },
+    Y4m(BufWriter<File>),
+}
+
+impl AnimationEncoder {
+    pub fn new(
+        path: &Path,
+        config: &AnimationConfig,
+        resolution: Resolution,
+        frames: usize,
+        fps: f64,
+    ) -> Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        match config.format {
+            AnimationFormat::Png => Err(Error::msg("PNG frames are written separately")),
+            AnimationFormat::Apng => {
+                let mut encoder =
+                    png::Encoder::new(file, resolution.width as _, resolution.height as _);
+                encoder.set_color(ColorType::Rgb);
+                encoder.set_depth(BitDepth::Eight);
+                encoder.set_animated(frames as _, config.loops as _)?;
+                // Frame delays are a fraction of a second, hundredths keep fractional rates exact
+                encoder.set_frame_delay(100, u16::try_from((fps * 100.0).round() as u64)?)?;
+                Ok(Self::Apng(encoder.write_header()?))
+            }
+            AnimationFormat::Gif => {
+                let mut encoder = gif::Encoder::new(
+                    file,
+                    u16::try_from(resolution.width)?,
+                    u16::try_from(resolution.height)?,
+                    &[],
+                )?;
+                // GIF counts the repetitions after the first play, and plays once without any
+                match config.loops {
+                    0 => encoder.set_repeat(gif::Repeat::Infinite)?,
+                    1 => (),
+                    loops => encoder.set_repeat(gif::Repeat::Finite(loops - 1))?,
+                }
+                Ok(Self::Gif {
+                    encoder,
+                    // A delay of 0 plays as fast as possible, so faster rates are capped at 100 fps
+                    delay: (100.0 / fps).round().max(1.0) as u16,
+                    dithering: config.dithering,
+                })
+            }
+            AnimationFormat::Y4m => {
+                let mut file = file;
+                let (numerator, denominator) = if fps.fract() == 0.0 {
+                    (fps as u64, 1)
+                } else {
+                    ((fps * 1000.0).round() as u64, 1000)
+                };
+                // Full chroma resolution, so the colours of thin features aren't smeared
+                writeln!(
+                    file,
+                    "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+                    resolution.width, resolution.height, numerator, denominator
+                )?;
+                Ok(Self::Y4m(file))
+            }
+        }
+    }
+
+    pub fn write_frame(&mut self, matrix: &Array2<Colour>) -> Result<()> {
+        let (width, height) = matrix.dim();
+        match self {
+            Self::Apng(writer) => {
+                let mut data = Vec::with_capacity(width * height * 3);
+                for y in 0..height {
+                    for x in 0..width {
+                        let colour = matrix[[x, y]];
+                        data.extend_from_slice(&[colour.r, colour.g, colour.b]);
+                    }
+                }
+                writer.write_image_data(&data)?;
+            }
+            Self::Gif {
+                encoder,
+                delay,
+                dithering,
+            } => {
+                let (palette, indices) = quantise(matrix, *dithering);
+                let frame = gif::Frame {
+                    delay: *delay,
+                    width: width as _,
+                    height: height as _,
+                    palette: Some(palette),
+                    buffer: Cow::Owned(indices),
+                    ..Default::default()
+                };
+                encoder.write_frame(&frame)?;
+            }
+            Self::Y4m(file) => {
+                file.write_all(b"FRAME\n")?;
+                let mut planes: [Vec<u8>; 3] = Default::default();
+                for y in 0..height {
+                    for x in 0..width {
+                        let (luma, cb, cr) = ycbcr(matrix[[x, y]]);
+                        planes[0].push(luma);
+                        planes[1].push(cb);
+                        planes[2].push(cr);
+                    }
+                }
+                for plane in planes {
+                    file.write_all(&plane)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self {
+            Self::Apng(writer) => writer.finish()?,
+            Self::Gif { encoder, .. } => encoder.into_inner()?.flush()?,
+            Self::Y4m(mut file) => file.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Reduces a frame to its own palette of 256 colours, returning the palette and the indices
+fn quantise(matrix: &Array2<Colour>, dithering: Dithering) -> (Vec<u8>, Vec<u8>) {
+    let (width, height) = matrix.dim();
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let colour = matrix[[x, y]];
+            rgba.extend_from_slice(&[colour.r, colour.g, colour.b, 0xff]);
+        }
+    }
+    let quantiser = NeuQuant::new(10, 256, &rgba);
+
+    let mut indices = Vec::with_capacity(width * height);
+    match dithering {
+        Dithering::None => {
+            for pixel in rgba.chunks(4) {
+                indices.push(quantiser.index_of(pixel) as u8);
+            }
+        }
+        Dithering::FloydSteinberg => {
+            // Quantisation errors carried to the current and the next row
+            let mut current = vec![[0.0f32; 3]; width + 2];
+            let mut next = vec![[0.0f32; 3]; width + 2];
+            for y in 0..height {
+                for x in 0..width {
+                    let i = (y * width + x) * 4;
+                    let mut pixel = [0xff; 4];
+                    let mut wanted = [0.0; 3];
+                    for c in 0..3 {
+                        wanted[c] = (rgba[i + c] as f32 + current[x + 1][c]).clamp(0.0, 255.0);
+                        pixel[c] = wanted[c].round() as u8;
+                    }
+                    let index = quantiser.index_of(&pixel);
+                    indices.push(index as u8);
+
+                    let chosen = quantiser.lookup(index).unwrap_or(pixel);
+                    for c in 0..3 {
+                        let error = wanted[c] - chosen[c] as f32;
+                        current[x + 2][c] += error * 7.0 / 16.0;
+                        next[x][c] += error * 3.0 / 16.0;
+                        next[x + 1][c] += error * 5.0 / 16.0;
+                        next[x + 2][c] += error / 16.0;
+                    }
+                }
+                current = std::mem::replace(&mut next, vec![[0.0; 3]; width + 2]);
+            }
+        }
+    }
+    (quantiser.color_map_rgb(), indices)
+}
+
+/// BT.601 limited range conversion, which is what Y4M readers expect by default
+fn ycbcr(colour: Colour) -> (u8, u8, u8) {
+    let (r, g, b) = (
+        colour.r as f64 / 255.0,
+        colour.g as f64 / 255.0,
+        colour.b as f64 / 255.0,
+    );
+    let luma = 16.0 + 65.481 * r + 128.553 * g + 24.966 * b;
+    let cb = 128.0 - 37.797 * r - 74.203 * g + 112.0 * b;
+    let cr = 128.0 + 112.0 * r - 93.786 * g - 18.214 * b;
+    (luma.round() as u8, cb.round() as u8, cr.round() as u8)
+}
@@ -0,0 +1,162 @@
+use crate::{
+    colour::Colour,
+    config::{Config, Resolution},
+    renderer::{Mapping, Precision, Renderer},
+    view::View,
+};
+use anyhow::{Error, Result};
+use ndarray::{Array2, Zip};
+use png::{BitDepth, ColorType, Decoder};
+use std::{
+    f64::consts::{LN_2, PI},
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+/// Layout of the log-polar strip around the centre of the configured view
+///
+/// The top row is the circle going through the corners of the first frame, and each row
+/// further down shrinks the radius by the same factor so pixels stay square.
+struct Strip {
+    view: View,
+    resolution: Resolution,
+    log_radius: f64,
+    step: f64,
+}
+
+impl Strip {
+    fn new(config: &Config) -> Self {
+        let view = crate::render_view(View::initial(config), config);
+        let (width, height) = view.dimensions(config.render.resolution);
+        let log_radius = (width.hypot(height) / 2.0).ln();
+
+        // The circle through the corners of the first frame is π times its diagonal in pixels
+        let (columns, rows) = crate::render_size(config);
+        let samples = config
+            .exponential_map
+            .width
+            .unwrap_or_else(|| (PI * columns.hypot(rows)).ceil() as usize)
+            .max(1);
+        let step = 2.0 * PI / samples as f64;
+        let resolution = Resolution {
+            width: samples,
+            height: (config.exponential_map.octaves * LN_2 / step).ceil() as usize,
+        };
+        Self {
+            view,
+            resolution,
+            log_radius,
+            step,
+        }
+    }
+
+    /// Radius of a row of the strip
+    fn radius(&self, y: f64) -> f64 {
+        (self.log_radius - y * self.step).exp()
+    }
+
+    /// Colour of the plane at the given offset from the centre, interpolated from the strip
+    fn get(&self, strip: &Array2<Colour>, dx: f64, dy: f64) -> Colour {
+        let (width, height) = strip.dim();
+        let x = dy.atan2(dx).rem_euclid(2.0 * PI) / self.step;
+        // Points closer to the centre than the bottom of the strip use its last row
+        let y = (self.log_radius - dx.hypot(dy).ln()) / self.step;
+        let y = y.clamp(0.0, (height - 1) as f64);
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as usize % width, y0 as usize);
+        let (x1, y1) = ((x0 + 1) % width, (y0 + 1).min(height - 1));
+        let top = strip[[x0, y0]].mix(strip[[x1, y0]], fx);
+        let bottom = strip[[x0, y1]].mix(strip[[x1, y1]], fx);
+        top.mix(bottom, fy)
+    }
+}
+
+/// Renders the log-polar strip around the centre of the configured view in a single pass
+///
+/// Lighting isn't applied since the slopes of the strip don't match the ones of the frames.
+pub fn render(config: &Config, filepath: PathBuf) -> Result<(PathBuf, Precision)> {
+    let strip = Strip::new(config);
+    // The precision has to hold up for the smallest pixels, at the bottom of the strip
+    let bottom = strip.radius(strip.resolution.height as f64) * strip.step;
+    let view = View::new(strip.view.center.clone(), bottom, strip.view.rotation);
+    let renderer =
+        Renderer::new(&view, strip.resolution, config).with_mapping(Mapping::Exponential {
+            log_radius: strip.log_radius,
+            step: strip.step,
+        });
+
+    let samples = crate::sample_all(&renderer, strip.resolution, None, None)?;
+    crate::write_png(&filepath, &samples.map(|sample| sample.colour), &[])?;
+    Ok((filepath, renderer.precision()))
+}
+
+/// Rebuilds the frames of a zoom into the centre of the configured view from its strip
+///
+/// The zoom stops once the part of the centre missing from the strip is smaller than a pixel.
+pub fn frames(config: &Config, strip_path: &Path) -> Result<()> {
+    let strip = Strip::new(config);
+    let image = read_png(strip_path)?;
+    if image.dim() != (strip.resolution.width, strip.resolution.height) {
+        return Err(Error::msg(
+            "the strip doesn't match the configured exponential map and render resolution",
+        ));
+    }
+
+    let start = strip.view.scale_factor;
+    let end = 2.0 * strip.radius(strip.resolution.height as f64);
+    if end >= start {
+        return Err(Error::msg("the strip doesn't cover enough octaves to zoom"));
+    }
+    let frames = config.animation.frames.max(1);
+    println!(
+        "[{}] [ANIMATION] Rebuilding {} frames from {}, zoom = {:e} to {:e}",
+        now!(),
+        frames,
+        strip_path.display(),
+        config.view.zoom,
+        config.view.zoom * start / end
+    );
+
+    let resolution = config.render.resolution;
+    crate::animation::write_frames(config, frames, config.animation.fps, |frame| {
+        let t = if frames > 1 {
+            (frame - 1) as f64 / (frames - 1) as f64
+        } else {
+            1.0
+        };
+        let scale_factor = start * (end / start).powf(t);
+
+        let mut matrix = Array2::from_elem(
+            (resolution.width, resolution.height),
+            Colour { r: 0, g: 0, b: 0 },
+        );
+        Zip::indexed(&mut matrix).par_apply(|(x, y), colour| {
+            let dx = (x as f64 - resolution.width as f64 / 2.0) * scale_factor;
+            let dy = (y as f64 - resolution.height as f64 / 2.0) * scale_factor;
+            *colour = strip.get(&image, dx, dy);
+        });
+        Ok((matrix, "from the exponential map".to_owned()))
+    })
+}
+
+fn read_png(path: &Path) -> Result<Array2<Colour>> {
+    let mut reader = Decoder::new(File::open(path)?).read_info()?;
+    let info = reader.info();
+    if info.color_type != ColorType::Rgb || info.bit_depth != BitDepth::Eight {
+        return Err(Error::msg("expected an 8-bit RGB image"));
+    }
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut buffer)?;
+
+    Ok(Array2::from_shape_fn((width, height), |(x, y)| {
+        let i = (y * width + x) * 3;
+        Colour {
+            r: buffer[i],
+            g: buffer[i + 1],
+            b: buffer[i + 2],
+        }
+    }))
+}
@@ -0,0 +1,454 @@
+use anyhow::{anyhow, bail, Result};
+use num_complex::Complex64;
+use std::{f64::consts, fmt, iter::Peekable, str::CharIndices};
+
+/// Expression tree of a user-defined formula over z and c
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Z,
+    C,
+    Constant(Complex64),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Pow(Box<Expr>, Box<Expr>),
+    PowI(Box<Expr>, i32),
+    Call(Function, Box<Expr>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Function {
+    Exp,
+    Log,
+    Sqrt,
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Conj,
+    /// Absolute value of each component, |Re(z)| + i|Im(z)|
+    Abs,
+    Re,
+    Im,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Imaginary(f64),
+    Ident(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    Open,
+    Close,
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self> {
+        let tokens = tokenise(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let expr = parser.expression()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(expr),
+            Some(t) => Err(anyhow!("unexpected {} in formula", t)),
+        }
+    }
+
+    pub fn eval(&self, z: Complex64, c: Complex64) -> Complex64 {
+        match self {
+            Expr::Z => z,
+            Expr::C => c,
+            Expr::Constant(k) => *k,
+            Expr::Neg(a) => -a.eval(z, c),
+            Expr::Add(a, b) => a.eval(z, c) + b.eval(z, c),
+            Expr::Sub(a, b) => a.eval(z, c) - b.eval(z, c),
+            Expr::Mul(a, b) => a.eval(z, c) * b.eval(z, c),
+            Expr::Div(a, b) => a.eval(z, c) / b.eval(z, c),
+            Expr::Pow(a, b) => {
+                let exponent = b.eval(z, c);
+                if exponent.im == 0.0 {
+                    a.eval(z, c).powf(exponent.re)
+                } else {
+                    a.eval(z, c).powc(exponent)
+                }
+            }
+            Expr::PowI(a, n) => a.eval(z, c).powi(*n),
+            Expr::Call(f, a) => f.apply(a.eval(z, c)),
+        }
+    }
+
+    /// Whether the expression only uses functions which are holomorphic, unlike conj, abs, re
+    /// and im
+    pub fn holomorphic(&self) -> bool {
+        match self {
+            Expr::Z | Expr::C | Expr::Constant(_) => true,
+            Expr::Neg(a) | Expr::PowI(a, _) => a.holomorphic(),
+            Expr::Add(a, b)
+            | Expr::Sub(a, b)
+            | Expr::Mul(a, b)
+            | Expr::Div(a, b)
+            | Expr::Pow(a, b) => a.holomorphic() && b.holomorphic(),
+            Expr::Call(Function::Conj | Function::Abs | Function::Re | Function::Im, _) => false,
+            Expr::Call(_, a) => a.holomorphic(),
+        }
+    }
+
+    /// Degree of the expression in z when it is a polynomial, used for smoothing
+    pub fn degree(&self) -> Option<f64> {
+        match self {
+            Expr::Z => Some(1.0),
+            Expr::C | Expr::Constant(_) => Some(0.0),
+            Expr::Neg(a) => a.degree(),
+            Expr::Add(a, b) | Expr::Sub(a, b) => Some(a.degree()?.max(b.degree()?)),
+            Expr::Mul(a, b) => Some(a.degree()? + b.degree()?),
+            Expr::Div(a, b) => Some(a.degree()? - b.degree()?),
+            Expr::Pow(a, b) => match **b {
+                Expr::Constant(k) if k.im == 0.0 => Some(a.degree()? * k.re),
+                _ => None,
+            },
+            Expr::PowI(a, n) => Some(a.degree()? * *n as f64),
+            Expr::Call(Function::Conj, a) | Expr::Call(Function::Abs, a) => a.degree(),
+            Expr::Call(_, _) => None,
+        }
+    }
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "exp" => Some(Function::Exp),
+            "log" | "ln" => Some(Function::Log),
+            "sqrt" => Some(Function::Sqrt),
+            "sin" => Some(Function::Sin),
+            "cos" => Some(Function::Cos),
+            "tan" => Some(Function::Tan),
+            "sinh" => Some(Function::Sinh),
+            "cosh" => Some(Function::Cosh),
+            "tanh" => Some(Function::Tanh),
+            "conj" => Some(Function::Conj),
+            "abs" => Some(Function::Abs),
+            "re" => Some(Function::Re),
+            "im" => Some(Function::Im),
+            _ => None,
+        }
+    }
+
+    fn apply(self, z: Complex64) -> Complex64 {
+        match self {
+            Function::Exp => z.exp(),
+            Function::Log => z.ln(),
+            Function::Sqrt => z.sqrt(),
+            Function::Sin => z.sin(),
+            Function::Cos => z.cos(),
+            Function::Tan => z.tan(),
+            Function::Sinh => z.sinh(),
+            Function::Cosh => z.cosh(),
+            Function::Tanh => z.tanh(),
+            Function::Conj => z.conj(),
+            Function::Abs => Complex64::new(z.re.abs(), z.im.abs()),
+            Function::Re => Complex64::new(z.re, 0.0),
+            Function::Im => Complex64::new(z.im, 0.0),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "'{}'", n),
+            Token::Imaginary(n) => write!(f, "'{}i'", n),
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Plus => f.write_str("'+'"),
+            Token::Minus => f.write_str("'-'"),
+            Token::Star => f.write_str("'*'"),
+            Token::Slash => f.write_str("'/'"),
+            Token::Caret => f.write_str("'^'"),
+            Token::Open => f.write_str("'('"),
+            Token::Close => f.write_str("')'"),
+        }
+    }
+}
+
+fn tokenise(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    while let Some(&(start, ch)) = chars.peek() {
+        match ch {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '+' | '-' | '*' | '/' | '^' | '(' | ')' => {
+                chars.next();
+                tokens.push(match ch {
+                    '+' => Token::Plus,
+                    '-' => Token::Minus,
+                    '*' => Token::Star,
+                    '/' => Token::Slash,
+                    '^' => Token::Caret,
+                    '(' => Token::Open,
+                    _ => Token::Close,
+                });
+            }
+            '0'..='9' | '.' => {
+                let end = number_end(source, &mut chars);
+                let value: f64 = source[start..end]
+                    .parse()
+                    .map_err(|_| anyhow!("invalid number {} in formula", &source[start..end]))?;
+                if let Some((_, 'i')) = chars.peek() {
+                    chars.next();
+                    tokens.push(Token::Imaginary(value));
+                } else {
+                    tokens.push(Token::Number(value));
+                }
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !c.is_ascii_alphanumeric() && c != '_' {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Ident(source[start..end].to_ascii_lowercase()));
+            }
+            c => bail!(
+                "unexpected character '{}' at position {} in formula",
+                c,
+                start
+            ),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn number_end(source: &str, chars: &mut Peekable<CharIndices>) -> usize {
+    let mut end = source.len();
+    let mut exponent = false;
+    while let Some(&(i, c)) = chars.peek() {
+        let sign = (c == '+' || c == '-')
+            && exponent
+            && matches!(source[..i].chars().last(), Some('e') | Some('E'));
+        if c.is_ascii_digit() || c == '.' || sign {
+            chars.next();
+        } else if (c == 'e' || c == 'E') && !exponent {
+            exponent = true;
+            chars.next();
+        } else {
+            end = i;
+            break;
+        }
+    }
+    end
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, token: Token) -> Result<()> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            Some(t) => Err(anyhow!("expected {} but found {} in formula", token, t)),
+            None => Err(anyhow!("expected {} but formula ended", token)),
+        }
+    }
+
+    /// expression = term (("+" | "-") term)*
+    fn expression(&mut self) -> Result<Expr> {
+        let mut lhs = self.term()?;
+        loop {
+            match self.peek() {
+                Some(Token::Plus) => {
+                    self.next();
+                    lhs = Expr::Add(Box::new(lhs), Box::new(self.term()?));
+                }
+                Some(Token::Minus) => {
+                    self.next();
+                    lhs = Expr::Sub(Box::new(lhs), Box::new(self.term()?));
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    /// term = unary (("*" | "/") unary)*
+    fn term(&mut self) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Star) => {
+                    self.next();
+                    lhs = Expr::Mul(Box::new(lhs), Box::new(self.unary()?));
+                }
+                Some(Token::Slash) => {
+                    self.next();
+                    lhs = Expr::Div(Box::new(lhs), Box::new(self.unary()?));
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    /// unary = "-" unary | "+" unary | power
+    fn unary(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some(Token::Minus) => {
+                self.next();
+                Ok(match self.unary()? {
+                    Expr::Constant(k) => Expr::Constant(-k),
+                    expr => Expr::Neg(Box::new(expr)),
+                })
+            }
+            Some(Token::Plus) => {
+                self.next();
+                self.unary()
+            }
+            _ => self.power(),
+        }
+    }
+
+    /// power = atom ("^" unary)?
+    fn power(&mut self) -> Result<Expr> {
+        let base = self.atom()?;
+        if let Some(Token::Caret) = self.peek() {
+            self.next();
+            let exponent = self.unary()?;
+            return Ok(match exponent {
+                Expr::Constant(k)
+                    if k.im == 0.0 && k.re.fract() == 0.0 && k.re.abs() <= i32::MAX as f64 =>
+                {
+                    Expr::PowI(Box::new(base), k.re as i32)
+                }
+                exponent => Expr::Pow(Box::new(base), Box::new(exponent)),
+            });
+        }
+        Ok(base)
+    }
+
+    /// atom = number | identifier | function "(" expression ")" | "(" expression ")"
+    fn atom(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Constant(Complex64::new(n, 0.0))),
+            Some(Token::Imaginary(n)) => Ok(Expr::Constant(Complex64::new(0.0, n))),
+            Some(Token::Open) => {
+                let expr = self.expression()?;
+                self.expect(Token::Close)?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => match name.as_str() {
+                "z" => Ok(Expr::Z),
+                "c" => Ok(Expr::C),
+                "i" => Ok(Expr::Constant(Complex64::i())),
+                "pi" => Ok(Expr::Constant(Complex64::new(consts::PI, 0.0))),
+                "e" => Ok(Expr::Constant(Complex64::new(consts::E, 0.0))),
+                _ => {
+                    let function = Function::from_name(&name)
+                        .ok_or_else(|| anyhow!("unknown identifier {} in formula", name))?;
+                    self.expect(Token::Open)?;
+                    let argument = self.expression()?;
+                    self.expect(Token::Close)?;
+                    Ok(Expr::Call(function, Box::new(argument)))
+                }
+            },
+            Some(t) => Err(anyhow!("unexpected {} in formula", t)),
+            None => Err(anyhow!("formula ended unexpectedly")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, z: f64, c: f64) -> Complex64 {
+        let expr = Expr::parse(source).unwrap();
+        expr.eval(Complex64::new(z, 0.0), Complex64::new(c, 0.0))
+    }
+
+    fn error(source: &str) -> String {
+        Expr::parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn operators_follow_precedence() {
+        assert_eq!(eval("z + c * 2", 1.0, 3.0).re, 7.0);
+        assert_eq!(eval("(z + c) * 2", 1.0, 3.0).re, 8.0);
+        assert_eq!(eval("z - c - 1", 5.0, 3.0).re, 1.0);
+        assert_eq!(eval("z / c / 2", 12.0, 3.0).re, 2.0);
+        assert_eq!(eval("2 * z^2", 3.0, 0.0).re, 18.0);
+        // Powers are right associative
+        assert_eq!(eval("2^3^2", 0.0, 0.0).re, 512.0);
+    }
+
+    #[test]
+    fn unary_minus_binds_looser_than_powers() {
+        assert_eq!(
+            Expr::parse("-z^2").unwrap(),
+            Expr::Neg(Box::new(Expr::PowI(Box::new(Expr::Z), 2)))
+        );
+        assert_eq!(
+            Expr::parse("-2").unwrap(),
+            Expr::Constant(Complex64::new(-2.0, 0.0))
+        );
+        assert_eq!(eval("z * -c", 2.0, 3.0).re, -6.0);
+        assert_eq!(eval("--z", 2.0, 0.0).re, 2.0);
+    }
+
+    #[test]
+    fn integer_powers_are_kept_apart() {
+        assert_eq!(
+            Expr::parse("z^3").unwrap(),
+            Expr::PowI(Box::new(Expr::Z), 3)
+        );
+        assert_eq!(
+            Expr::parse("z^-2").unwrap(),
+            Expr::PowI(Box::new(Expr::Z), -2)
+        );
+        assert_eq!(
+            Expr::parse("z^2.5").unwrap(),
+            Expr::Pow(
+                Box::new(Expr::Z),
+                Box::new(Expr::Constant(Complex64::new(2.5, 0.0)))
+            )
+        );
+        assert_eq!(eval("z^-2", 2.0, 0.0).re, 0.25);
+    }
+
+    #[test]
+    fn unknown_identifiers_are_rejected() {
+        assert_eq!(error("w + c"), "unknown identifier w in formula");
+        assert_eq!(error("sin z"), "expected '(' but found 'z' in formula");
+    }
+
+    #[test]
+    fn trailing_input_is_rejected() {
+        assert_eq!(error("z c"), "unexpected 'c' in formula");
+        assert_eq!(error("z^2 + c)"), "unexpected ')' in formula");
+        assert_eq!(error("z +"), "formula ended unexpectedly");
+    }
+}
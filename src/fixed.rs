@@ -0,0 +1,243 @@
+use anyhow::{anyhow, Result};
+use num_bigint::{BigInt, Sign};
+use num_traits::{Signed, ToPrimitive, Zero};
+use serde::{Serialize, Serializer};
+use std::{
+    cmp::Ordering,
+    convert::TryFrom,
+    fmt,
+    ops::{Add, Mul, Sub},
+};
+
+/// Bits of precision kept for values that don't need more
+pub const MIN_BITS: usize = 64;
+
+/// Largest decimal exponent parsed, far past any zoom but small enough to compute quickly
+const MAX_EXPONENT: i64 = 10_000;
+
+/// Arbitrary precision fixed-point number, equal to mantissa / 2^bits
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct Fixed {
+    mantissa: BigInt,
+    bits: usize,
+}
+
+impl Fixed {
+    pub fn zero(bits: usize) -> Self {
+        Self {
+            mantissa: BigInt::zero(),
+            bits,
+        }
+    }
+
+    /// Value of a finite float, callers filter out infinities and NaN coming from user input
+    pub fn from_f64(value: f64, bits: usize) -> Self {
+        debug_assert!(value.is_finite(), "{} has no fixed-point value", value);
+        let (mantissa, exponent, sign) = num_traits::Float::integer_decode(value);
+        let mantissa = BigInt::from(mantissa) * sign;
+        let shift = bits as i64 + exponent as i64;
+        let mantissa = match shift.cmp(&0) {
+            Ordering::Greater => mantissa << shift as usize,
+            Ordering::Less => mantissa >> (-shift) as usize,
+            Ordering::Equal => mantissa,
+        };
+        Self { mantissa, bits }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        let (negative, value) = match value.as_bytes().first() {
+            Some(b'-') => (true, &value[1..]),
+            Some(b'+') => (false, &value[1..]),
+            _ => (false, value),
+        };
+        let (value, exponent) = match value.find(['e', 'E']) {
+            Some(i) => (&value[..i], value[i + 1..].parse::<i64>()?),
+            None => (value, 0),
+        };
+        let (integer, fraction) = match value.find('.') {
+            Some(i) => (&value[..i], &value[i + 1..]),
+            None => (value, ""),
+        };
+
+        let digits = format!("{}{}", integer, fraction);
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(anyhow!("invalid decimal number"));
+        }
+        if exponent.abs() > MAX_EXPONENT {
+            return Err(anyhow!(
+                "decimal exponent {} is out of range, it should be within ±{}",
+                exponent,
+                MAX_EXPONENT
+            ));
+        }
+        let exponent = exponent - fraction.len() as i64;
+
+        // Enough bits to represent every given digit, plus the usual headroom
+        let bits = MIN_BITS + (-exponent).max(0) as usize * 10 / 3;
+        let mut mantissa = BigInt::parse_bytes(digits.as_bytes(), 10)
+            .ok_or_else(|| anyhow!("invalid decimal number"))?
+            << bits;
+        if exponent >= 0 {
+            mantissa *= BigInt::from(10).pow(exponent as u32);
+        } else {
+            let divisor = BigInt::from(10).pow((-exponent) as u32);
+            mantissa = (mantissa + &divisor / 2) / divisor;
+        }
+        if negative {
+            mantissa = -mantissa;
+        }
+
+        Ok(Self { mantissa, bits })
+    }
+
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    /// Same value with the given precision, truncating when it is lowered
+    pub fn with_bits(&self, bits: usize) -> Self {
+        let mantissa = match bits.cmp(&self.bits) {
+            Ordering::Greater => &self.mantissa << (bits - self.bits),
+            Ordering::Less => &self.mantissa >> (self.bits - bits),
+            Ordering::Equal => self.mantissa.clone(),
+        };
+        Self { mantissa, bits }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let shift = (self.mantissa.bits() as i64 - 64).max(0);
+        let mantissa = (&self.mantissa >> shift as usize).to_f64().unwrap_or(0.0);
+        let exponent = shift - self.bits as i64;
+        // Split the scaling so intermediate powers don't overflow or underflow
+        let half = (exponent / 2) as i32;
+        mantissa * 2f64.powi(half) * 2f64.powi(exponent as i32 - half)
+    }
+
+    /// Decimal representation with the given number of fractional digits
+    pub fn to_decimal(&self, digits: usize) -> String {
+        // Round to the last digit rather than truncating
+        let half = (BigInt::from(1) << self.bits) / (BigInt::from(10).pow(digits as u32) * 2);
+        let magnitude: BigInt = self.mantissa.abs() + half;
+        let integer = &magnitude >> self.bits;
+        let mut fraction = magnitude - (&integer << self.bits);
+
+        let mut s = String::new();
+        if self.mantissa.sign() == Sign::Minus {
+            s.push('-');
+        }
+        s.push_str(&integer.to_str_radix(10));
+
+        let mut decimals = String::with_capacity(digits);
+        for _ in 0..digits {
+            if fraction.is_zero() {
+                break;
+            }
+            fraction *= 10;
+            let digit = &fraction >> self.bits;
+            fraction -= &digit << self.bits;
+            decimals.push_str(&digit.to_str_radix(10));
+        }
+        let decimals = decimals.trim_end_matches('0');
+        if !decimals.is_empty() {
+            s.push('.');
+            s.push_str(decimals);
+        }
+        s
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = f
+            .precision()
+            .unwrap_or_else(|| (self.bits * 3 / 10).saturating_sub(3));
+        f.write_str(&self.to_decimal(digits))
+    }
+}
+
+impl Serialize for Fixed {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl TryFrom<String> for Fixed {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl Add for &Fixed {
+    type Output = Fixed;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        let bits = self.bits.max(rhs.bits);
+        Fixed {
+            mantissa: self.with_bits(bits).mantissa + rhs.with_bits(bits).mantissa,
+            bits,
+        }
+    }
+}
+
+impl Sub for &Fixed {
+    type Output = Fixed;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        let bits = self.bits.max(rhs.bits);
+        Fixed {
+            mantissa: self.with_bits(bits).mantissa - rhs.with_bits(bits).mantissa,
+            bits,
+        }
+    }
+}
+
+impl Mul for &Fixed {
+    type Output = Fixed;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let bits = self.bits.max(rhs.bits);
+        Fixed {
+            mantissa: (&self.mantissa * &rhs.mantissa) >> (self.bits + rhs.bits - bits),
+            bits,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimals_parse_back_exactly() {
+        let third = Fixed::from_f64(1.0 / 3.0, 200);
+        let values = [
+            Fixed::zero(64),
+            Fixed::from_f64(-1.75, 64),
+            Fixed::from_f64(-0.743_643_887_037_151, 128),
+            Fixed::from_f64(1e-40, 300),
+            // Every one of the bits is set, past what f64 holds
+            &(&third * &third) - &Fixed::from_f64(-2.0, 200),
+        ];
+        for x in &values {
+            let decimal = x.to_decimal(x.bits());
+            let parsed = Fixed::parse(&decimal).unwrap();
+            assert_eq!(parsed.with_bits(x.bits()), *x, "{}", decimal);
+        }
+    }
+
+    #[test]
+    fn huge_exponents_are_rejected() {
+        assert!(Fixed::parse("1e10000").is_ok());
+        assert!(Fixed::parse("-2.5e-10000").is_ok());
+        for value in ["1e10001", "1e-999999999", "1e999999999999999999999"] {
+            assert!(Fixed::parse(value).is_err(), "{} was accepted", value);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{expr::Expr, mandelbrot::Area};
+use num_complex::Complex64;
+use num_traits::{One, Zero};
+
+/// An escape-time fractal, iterated from an initial z until it leaves the bailout radius
+pub trait EscapeTimeFractal: Send + Sync {
+    /// Starting value of z for a point c of the parameter plane
+    fn initial(&self, _c: Complex64) -> Complex64 {
+        Complex64::zero()
+    }
+
+    /// Derivative of the initial z with respect to c
+    fn initial_derivative(&self) -> Complex64 {
+        Complex64::zero()
+    }
+
+    /// Computes the next value of z
+    fn step(&self, z: Complex64, c: Complex64) -> Complex64;
+
+    /// Derivative of the step with respect to z, used for distance estimation
+    fn derivative(&self, z: Complex64, c: Complex64) -> Complex64 {
+        let h = 1e-8 * (1.0 + z.norm());
+        (self.step(z + h, c) - self.step(z, c)) / h
+    }
+
+    /// Whether the step is holomorphic in z, which distance estimation relies on
+    fn holomorphic(&self) -> bool {
+        true
+    }
+
+    /// Squared escape radius, past which the orbit is considered diverging
+    fn bailout(&self) -> f64 {
+        4.0
+    }
+
+    /// Continuous iteration count for an orbit that escaped as z after i iterations
+    fn smooth(&self, i: usize, z: Complex64) -> f64 {
+        let log_zn = z.norm_sqr().log10() / 2f64;
+        let nu = (log_zn / 2f64.log10()).log2();
+        i as f64 + 1f64 - nu
+    }
+
+    /// Area of the parameter plane shown by default
+    fn area(&self) -> Area {
+        Area {
+            x_start: -2.5,
+            x_end: 1.0,
+            y_start: -1.0,
+            y_end: 1.0,
+        }
+    }
+}
+
+/// f(z) = z² + c
+pub struct Mandelbrot;
+
+/// f(z) = (|Re(z)| + i|Im(z)|)² + c
+pub struct BurningShip;
+
+/// f(z) = conj(z)² + c
+pub struct Tricorn;
+
+/// f(z) = zⁿ + c for a real n
+pub struct Multibrot {
+    pub exponent: f64,
+}
+
+impl EscapeTimeFractal for Mandelbrot {
+    fn step(&self, z: Complex64, c: Complex64) -> Complex64 {
+        z * z + c
+    }
+
+    fn derivative(&self, z: Complex64, _c: Complex64) -> Complex64 {
+        z * 2.0
+    }
+}
+
+impl EscapeTimeFractal for BurningShip {
+    fn holomorphic(&self) -> bool {
+        false
+    }
+
+    fn step(&self, z: Complex64, c: Complex64) -> Complex64 {
+        let z = Complex64::new(z.re.abs(), z.im.abs());
+        z * z + c
+    }
+
+    fn area(&self) -> Area {
+        Area {
+            x_start: -2.5,
+            x_end: 1.5,
+            y_start: -2.0,
+            y_end: 1.0,
+        }
+    }
+}
+
+impl EscapeTimeFractal for Tricorn {
+    fn holomorphic(&self) -> bool {
+        false
+    }
+
+    fn step(&self, z: Complex64, c: Complex64) -> Complex64 {
+        let z = z.conj();
+        z * z + c
+    }
+
+    fn area(&self) -> Area {
+        Area {
+            x_start: -2.25,
+            x_end: 1.75,
+            y_start: -1.5,
+            y_end: 1.5,
+        }
+    }
+}
+
+impl EscapeTimeFractal for Multibrot {
+    fn initial(&self, c: Complex64) -> Complex64 {
+        // Negative powers of 0 are infinite, so those orbits start one step later instead
+        if self.exponent < 0.0 {
+            c
+        } else {
+            Complex64::zero()
+        }
+    }
+
+    fn initial_derivative(&self) -> Complex64 {
+        if self.exponent < 0.0 {
+            Complex64::one()
+        } else {
+            Complex64::zero()
+        }
+    }
+
+    fn step(&self, z: Complex64, c: Complex64) -> Complex64 {
+        z.powf(self.exponent) + c
+    }
+
+    fn derivative(&self, z: Complex64, _c: Complex64) -> Complex64 {
+        z.powf(self.exponent - 1.0) * self.exponent
+    }
+
+    fn smooth(&self, i: usize, z: Complex64) -> f64 {
+        let log_zn = z.norm_sqr().ln() / 2f64;
+        let nu = (log_zn / 2f64.ln()).ln() / self.exponent.abs().ln();
+        i as f64 + 1f64 - nu
+    }
+
+    fn area(&self) -> Area {
+        Area {
+            x_start: -2.0,
+            x_end: 2.0,
+            y_start: -1.5,
+            y_end: 1.5,
+        }
+    }
+}
+
+impl EscapeTimeFractal for Expr {
+    fn step(&self, z: Complex64, c: Complex64) -> Complex64 {
+        self.eval(z, c)
+    }
+
+    fn holomorphic(&self) -> bool {
+        Expr::holomorphic(self)
+    }
+
+    fn smooth(&self, i: usize, z: Complex64) -> f64 {
+        match self.degree() {
+            Some(degree) if degree.abs() > 1.0 => Multibrot { exponent: degree }.smooth(i, z),
+            _ => Mandelbrot.smooth(i, z),
+        }
+    }
+}
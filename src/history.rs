@@ -0,0 +1,37 @@
+use crate::view::View;
+use std::collections::VecDeque;
+
+/// Views left by navigating the preview, which can be gone back and forth through
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    undo: VecDeque<View>,
+    redo: Vec<View>,
+}
+
+impl History {
+    /// Remembers the view before a navigation step, forgetting the oldest one past the limit
+    pub fn push(&mut self, view: &View, limit: usize) {
+        self.redo.clear();
+        self.undo.push_back(view.clone());
+        while self.undo.len() > limit {
+            self.undo.pop_front();
+        }
+    }
+
+    pub fn undo(&mut self, current: &View) -> Option<View> {
+        let view = self.undo.pop_back()?;
+        self.redo.push(current.clone());
+        Some(view)
+    }
+
+    pub fn redo(&mut self, current: &View) -> Option<View> {
+        let view = self.redo.pop()?;
+        self.undo.push_back(current.clone());
+        Some(view)
+    }
+
+    /// Every remembered view, to keep them in line with changes to the preview
+    pub fn views_mut(&mut self) -> impl Iterator<Item = &mut View> {
+        self.undo.iter_mut().chain(self.redo.iter_mut())
+    }
+}
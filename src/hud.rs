@@ -0,0 +1,254 @@
+use crate::{config::Config, view::View};
+use anyhow::{Error, Result};
+use sdl2::{pixels::Color, rect::Rect, render::BlendMode, render::WindowCanvas};
+use std::time::Duration;
+
+/// Width and height of a character of the font, in font pixels
+const GLYPH: (i32, i32) = (5, 7);
+/// Space around and between the lines and characters, in font pixels
+const SPACING: i32 = 1;
+
+/// Overlay listing the state of the preview, drawn with a built-in bitmap font
+#[derive(Debug, Default)]
+pub struct Hud {
+    pub visible: bool,
+    /// Whether the preview has passes left
+    pub busy: bool,
+    /// Side of the blocks of the last pass of the preview
+    pub block: usize,
+    /// Time the last complete preview took
+    pub preview_time: Option<Duration>,
+    /// Progress of the renders running or waiting in the background
+    pub renders: Vec<String>,
+}
+
+impl Hud {
+    pub fn draw(
+        &self,
+        canvas: &mut WindowCanvas,
+        view: &View,
+        config: &Config,
+        cursor: (i32, i32),
+    ) -> Result<()> {
+        if !self.visible {
+            return Ok(());
+        }
+        let lines = self.lines(view, config, cursor);
+
+        // Font pixels are scaled up so text stays legible in large previews
+        let scale = (config.preview.resolution.height as i32 / 240).max(1);
+        let (advance, line_height) = ((GLYPH.0 + SPACING) * scale, (GLYPH.1 + SPACING) * scale);
+        let columns = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) as i32;
+        let background = Rect::new(
+            0,
+            0,
+            (columns * advance + SPACING * scale) as u32,
+            (lines.len() as i32 * line_height + SPACING * scale) as u32,
+        );
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 0xa0));
+        canvas.fill_rect(background).map_err(Error::msg)?;
+        canvas.set_blend_mode(BlendMode::None);
+
+        let mut pixels = Vec::new();
+        for (row, line) in lines.iter().enumerate() {
+            let top = SPACING * scale + row as i32 * line_height;
+            for (column, c) in line.chars().enumerate() {
+                let left = SPACING * scale + column as i32 * advance;
+                for (y, bits) in glyph(c).iter().enumerate() {
+                    for x in 0..GLYPH.0 {
+                        if bits & (1 << (GLYPH.0 - 1 - x)) != 0 {
+                            pixels.push(Rect::new(
+                                left + x * scale,
+                                top + y as i32 * scale,
+                                scale as u32,
+                                scale as u32,
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        canvas.set_draw_color(Color::RGB(0xff, 0xff, 0xff));
+        canvas.fill_rects(&pixels).map_err(Error::msg)?;
+        Ok(())
+    }
+
+    fn lines(&self, view: &View, config: &Config, cursor: (i32, i32)) -> Vec<String> {
+        let digits = view.digits();
+        let (x, y) = view.point(cursor.0 as f64, cursor.1 as f64, config.preview.resolution);
+        let preview = match self.preview_time {
+            _ if self.busy && self.block > 1 => format!("rendering 1/{}", self.block),
+            _ if self.busy => "rendering".to_owned(),
+            Some(time) => format!("{} ms", time.as_millis()),
+            None => "none".to_owned(),
+        };
+        let mut lines = vec![
+            format!("re      {:.*}", digits, view.center.0),
+            format!("im      {:.*}", digits, view.center.1),
+            format!("zoom    {:e}", view.zoom_level(config)),
+            format!("iter    {}", config.max_iterations),
+            format!("preview {}", preview),
+            format!("mouse   {:.*}", digits, x),
+            format!("        {:.*}", digits, y),
+        ];
+        if self.renders.is_empty() {
+            lines.push("renders none".to_owned());
+        }
+        for (i, render) in self.renders.iter().enumerate() {
+            let label = if i == 0 { "renders" } else { "" };
+            lines.push(format!("{:<7} {}", label, render));
+        }
+        lines
+    }
+}
+
+/// Rows of a character from the top, the lowest 5 bits going left to right
+///
+/// Letters are drawn in upper case, and characters without a glyph are left blank.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        '0' => [
+            0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+        ],
+        '1' => [
+            0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        '2' => [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+        ],
+        '3' => [
+            0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+        ],
+        '4' => [
+            0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+        ],
+        '5' => [
+            0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+        ],
+        '6' => [
+            0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+        ],
+        '7' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+        ],
+        '8' => [
+            0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+        ],
+        '9' => [
+            0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+        ],
+        'A' => [
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'B' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+        ],
+        'C' => [
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ],
+        'D' => [
+            0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100,
+        ],
+        'E' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+        ],
+        'F' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'G' => [
+            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+        ],
+        'H' => [
+            0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'I' => [
+            0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        'J' => [
+            0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+        ],
+        'K' => [
+            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+        ],
+        'L' => [
+            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+        ],
+        'M' => [
+            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+        ],
+        'N' => [
+            0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+        ],
+        'O' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'P' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'Q' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+        ],
+        'R' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+        ],
+        'S' => [
+            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+        ],
+        'T' => [
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+        'U' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'V' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ],
+        'W' => [
+            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+        ],
+        'X' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ],
+        'Y' => [
+            0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100,
+        ],
+        'Z' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ],
+        '.' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100,
+        ],
+        ',' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000,
+        ],
+        '-' => [
+            0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000,
+        ],
+        '+' => [
+            0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000,
+        ],
+        ':' => [
+            0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000,
+        ],
+        '=' => [
+            0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000,
+        ],
+        '/' => [
+            0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000,
+        ],
+        '%' => [
+            0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011,
+        ],
+        '#' => [
+            0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010,
+        ],
+        '(' => [
+            0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010,
+        ],
+        ')' => [
+            0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000,
+        ],
+        _ => [0; 7],
+    }
+}
@@ -0,0 +1,506 @@
+use crate::{
+    checkpoint::{self, Cancelled, Rows},
+    config::{Config, QuitPolicy},
+    view::View,
+};
+use anyhow::Error;
+use std::{
+    any::Any,
+    collections::VecDeque,
+    io::{self, Write},
+    iter,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Progress of a render, shared with the threads sampling it
+#[derive(Debug, Default)]
+pub struct Progress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    pub fn start(&self, total: usize) {
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn fraction(&self) -> f64 {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0.0;
+        }
+        self.done.load(Ordering::Relaxed) as f64 / total as f64
+    }
+}
+
+/// Renders started from the preview, waiting in line and run a few at a time
+///
+/// Dropping the queue deals with the unfinished renders and waits for the workers to stop.
+pub struct RenderQueue {
+    /// What happens to unfinished renders when the queue is dropped
+    pub on_quit: QuitPolicy,
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    waiting: VecDeque<Job>,
+    running: Vec<Running>,
+    next_id: usize,
+    /// Workers stop once the waiting jobs are done
+    closed: bool,
+    /// Number of workers wanted, the extra ones stop once their job is done
+    concurrency: usize,
+    /// Number of workers still taking jobs
+    workers: usize,
+    /// Whether cancelled jobs save a checkpoint
+    checkpoint: bool,
+}
+
+struct Job {
+    id: usize,
+    view: View,
+    config: Config,
+    filepath: PathBuf,
+}
+
+struct Running {
+    id: usize,
+    filepath: PathBuf,
+    progress: Arc<Progress>,
+    started: Instant,
+    /// Last tenth of the render which was printed
+    reported: usize,
+}
+
+impl RenderQueue {
+    pub fn new(concurrency: usize, on_quit: QuitPolicy) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                next_id: 1,
+                ..Default::default()
+            }),
+            changed: Condvar::new(),
+        });
+        let mut queue = Self {
+            on_quit,
+            shared,
+            workers: Vec::new(),
+        };
+        queue.resize(concurrency);
+        queue
+    }
+
+    /// Changes the number of jobs run at once, running jobs finish before their worker stops
+    pub fn resize(&mut self, concurrency: usize) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.concurrency = concurrency.max(1);
+        while state.workers < state.concurrency {
+            state.workers += 1;
+            let shared = self.shared.clone();
+            self.workers.push(thread::spawn(move || work(&shared)));
+        }
+        // Idle workers check whether they are still wanted
+        self.shared.changed.notify_all();
+        self.workers.retain(|worker| !worker.is_finished());
+    }
+
+    /// Queues a view already scaled to the render resolution
+    pub fn push(&self, view: View, config: Config, filepath: PathBuf) {
+        let mut state = self.shared.state.lock().unwrap();
+        // Timestamped paths of renders queued within the same second are the same
+        let filepath = unique_path(filepath, |path| {
+            state.waiting.iter().any(|job| job.filepath == path)
+                || state.running.iter().any(|running| running.filepath == path)
+        });
+        let id = state.next_id;
+        state.next_id += 1;
+        println!(
+            "[{}] [RENDER] [#{}] Queued {}, {} ahead",
+            now!(),
+            id,
+            filepath.display(),
+            state.waiting.len() + state.running.len()
+        );
+        state.waiting.push_back(Job {
+            id,
+            view,
+            config,
+            filepath,
+        });
+        self.shared.changed.notify_one();
+    }
+
+    /// Cancels the last job queued, stopping it if it already started
+    pub fn cancel_last(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(job) = state.waiting.pop_back() {
+            println!(
+                "[{}] [RENDER] [#{}] Cancelled before starting",
+                now!(),
+                job.id
+            );
+        } else if let Some(running) = state.running.iter().rev().find(|r| !r.progress.cancelled()) {
+            println!("[{}] [RENDER] [#{}] Cancelling", now!(), running.id);
+            running.progress.cancel();
+        } else {
+            println!("[{}] [RENDER] Nothing to cancel", now!());
+        }
+    }
+
+    /// Prints the progress of the running jobs every tenth of the way
+    pub fn report(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        for running in &mut state.running {
+            let fraction = running.progress.fraction();
+            let tenth = (fraction * 10.0) as usize;
+            if tenth > running.reported && tenth < 10 {
+                running.reported = tenth;
+                println!(
+                    "[{}] [RENDER] [#{}] {:.0}% done, {} left",
+                    now!(),
+                    running.id,
+                    fraction * 100.0,
+                    eta(running)
+                );
+            }
+        }
+    }
+
+    /// Whether any job is running or waiting
+    pub fn busy(&self) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        !state.running.is_empty() || !state.waiting.is_empty()
+    }
+
+    /// A line for each running and waiting job
+    pub fn status(&self) -> Vec<String> {
+        let state = self.shared.state.lock().unwrap();
+        let running = state.running.iter().map(|running| {
+            format!(
+                "#{} {:.0}% {} left",
+                running.id,
+                running.progress.fraction() * 100.0,
+                eta(running)
+            )
+        });
+        let waiting = state
+            .waiting
+            .iter()
+            .map(|job| format!("#{} waiting", job.id));
+        running.chain(waiting).collect()
+    }
+}
+
+impl Drop for RenderQueue {
+    /// Stops taking jobs and deals with the unfinished ones, returning once every worker stopped
+    fn drop(&mut self) {
+        let unfinished = {
+            let state = self.shared.state.lock().unwrap();
+            state.waiting.len() + state.running.len()
+        };
+        let policy = match self.on_quit {
+            QuitPolicy::Ask if unfinished > 0 => ask(unfinished),
+            policy => policy,
+        };
+
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.closed = true;
+            if let QuitPolicy::Cancel | QuitPolicy::Checkpoint = policy {
+                state.checkpoint = policy == QuitPolicy::Checkpoint;
+                let waiting: Vec<_> = state.waiting.drain(..).collect();
+                for job in &waiting {
+                    if state.checkpoint {
+                        checkpoint(job, &Rows::new(job.config.render.resolution));
+                    } else {
+                        println!(
+                            "[{}] [RENDER] [#{}] Cancelled before starting",
+                            now!(),
+                            job.id
+                        );
+                    }
+                }
+                for running in &state.running {
+                    running.progress.cancel();
+                }
+            }
+            self.shared.changed.notify_all();
+        }
+
+        if unfinished > 0 {
+            println!("[{}] [RENDER] Finishing before quitting", now!());
+        }
+        while self.workers.iter().any(|worker| !worker.is_finished()) {
+            self.report();
+            thread::sleep(Duration::from_millis(250));
+        }
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                eprintln!("[{}] [RENDER] [ERROR] A render worker panicked", now!());
+            }
+        }
+    }
+}
+
+fn work(shared: &Shared) {
+    loop {
+        let (job, progress) = {
+            let mut state = shared.state.lock().unwrap();
+            let job = loop {
+                if state.workers > state.concurrency {
+                    state.workers -= 1;
+                    return;
+                }
+                if let Some(job) = state.waiting.pop_front() {
+                    break job;
+                }
+                if state.closed {
+                    return;
+                }
+                state = shared.changed.wait(state).unwrap();
+            };
+            let progress = Arc::new(Progress::default());
+            state.running.push(Running {
+                id: job.id,
+                filepath: job.filepath.clone(),
+                progress: progress.clone(),
+                started: Instant::now(),
+                reported: 0,
+            });
+            (job, progress)
+        };
+
+        println!(
+            "[{}] [RENDER] [#{}] Started rendering {}",
+            now!(),
+            job.id,
+            job.filepath.display()
+        );
+        let start = Instant::now();
+        // A panicking render fails its job only, the worker carries on with the next one
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            crate::render_inner(
+                job.view.clone(),
+                job.config.clone(),
+                job.filepath.clone(),
+                Some(&progress),
+                None,
+            )
+        }))
+        .unwrap_or_else(|panic| Err(Error::msg(panic_message(&*panic))));
+        match result {
+            Ok((p, precision)) => println!(
+                "[{}] [RENDER] [#{}] Done rendering {} using {} precision in {}",
+                now!(),
+                job.id,
+                p.display(),
+                precision,
+                duration(start.elapsed())
+            ),
+            Err(e) if progress.cancelled() => {
+                if shared.state.lock().unwrap().checkpoint {
+                    let rows = match e.downcast::<Cancelled>() {
+                        Ok(Cancelled(rows)) => rows,
+                        Err(_) => Rows::new(job.config.render.resolution),
+                    };
+                    checkpoint(&job, &rows);
+                } else {
+                    println!("[{}] [RENDER] [#{}] Cancelled", now!(), job.id);
+                }
+            }
+            Err(e) => eprintln!("[{}] [RENDER] [#{}] [ERROR] {}", now!(), job.id, e),
+        }
+
+        let mut state = shared.state.lock().unwrap();
+        state.running.retain(|running| running.id != job.id);
+    }
+}
+
+/// The path, or the first one with a -2, -3… suffix, which no other job writes and which has no
+/// image, partial image or checkpoint on disk
+fn unique_path(filepath: PathBuf, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    let stem = filepath.file_stem().unwrap_or_default().to_owned();
+    let candidates = (2..).map(|n| {
+        let mut name = stem.clone();
+        name.push(format!("-{}", n));
+        let mut path = filepath.with_file_name(name);
+        if let Some(extension) = filepath.extension() {
+            path.set_extension(extension);
+        }
+        path
+    });
+    let free = |path: &PathBuf| {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".part");
+        !taken(path)
+            && !path.exists()
+            && !Path::new(&partial).exists()
+            && !checkpoint::path(path).exists()
+    };
+    iter::once(filepath.clone())
+        .chain(candidates)
+        .find(free)
+        .unwrap_or(filepath)
+}
+
+/// Describes the payload of a panic, which is usually its message
+pub fn panic_message(panic: &(dyn Any + Send)) -> String {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown reason");
+    format!("panicked: {}", message)
+}
+
+/// Asks in the terminal what to do with the unfinished jobs, waiting for them without an answer
+fn ask(unfinished: usize) -> QuitPolicy {
+    loop {
+        print!(
+            "[{}] [RENDER] {} renders unfinished, [w]ait for them, [c]ancel them or [s]ave them \
+             to render later? ",
+            now!(),
+            unfinished
+        );
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        if let Ok(0) | Err(_) = io::stdin().read_line(&mut answer) {
+            println!();
+            return QuitPolicy::Wait;
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "w" | "wait" => return QuitPolicy::Wait,
+            "c" | "cancel" => return QuitPolicy::Cancel,
+            "s" | "save" => return QuitPolicy::Checkpoint,
+            _ => (),
+        }
+    }
+}
+
+/// Saves the rows an unfinished job completed next to where its image would have been
+fn checkpoint(job: &Job, rows: &Rows) {
+    let path = checkpoint::path(&job.filepath);
+    let saved = crate::metadata::configuration(&job.view, &job.config)
+        .and_then(|configuration| checkpoint::save(&path, &configuration, rows));
+    match saved {
+        Ok(()) => println!(
+            "[{}] [RENDER] [#{}] Saved {} of {} rows to {}, finish it with `fractal resume {}`",
+            now!(),
+            job.id,
+            rows.count(),
+            rows.complete.len(),
+            path.display(),
+            path.display()
+        ),
+        Err(e) => eprintln!(
+            "[{}] [RENDER] [#{}] [ERROR] Couldn't save {}: {}",
+            now!(),
+            job.id,
+            path.display(),
+            e
+        ),
+    }
+}
+
+/// Time left for a running job, extrapolated from its progress so far
+fn eta(running: &Running) -> String {
+    let fraction = running.progress.fraction();
+    if fraction <= 0.0 {
+        return "unknown time".to_owned();
+    }
+    let elapsed = running.started.elapsed().as_secs_f64();
+    duration(Duration::from_secs_f64(
+        elapsed * (1.0 - fraction) / fraction,
+    ))
+}
+
+fn duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn live(queue: &RenderQueue) -> usize {
+        for _ in 0..500 {
+            let wanted = queue.shared.state.lock().unwrap().concurrency;
+            let live = queue.workers.iter().filter(|w| !w.is_finished()).count();
+            if live == wanted {
+                return live;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        queue.workers.iter().filter(|w| !w.is_finished()).count()
+    }
+
+    #[test]
+    fn workers_follow_the_concurrency() {
+        let mut queue = RenderQueue::new(3, QuitPolicy::Wait);
+        assert_eq!(live(&queue), 3);
+        queue.resize(1);
+        assert_eq!(live(&queue), 1);
+        queue.resize(2);
+        assert_eq!(live(&queue), 2);
+    }
+
+    #[test]
+    fn paths_get_a_suffix_once_taken() {
+        let directory = std::env::temp_dir().join(format!("fractal-jobs-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("render.png");
+        assert_eq!(unique_path(path.clone(), |_| false), path);
+
+        let queued = directory.join("render-2.png");
+        let taken = |p: &Path| p == path || p == queued;
+        assert_eq!(
+            unique_path(path.clone(), taken),
+            directory.join("render-3.png")
+        );
+
+        fs::write(directory.join("render-3.png.part"), "").unwrap();
+        let free = unique_path(path.clone(), taken);
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(free, directory.join("render-4.png"));
+    }
+
+    #[test]
+    fn panics_are_reported_as_errors() {
+        let panic = panic::catch_unwind(|| panic!("out of {}", "range")).unwrap_err();
+        assert_eq!(panic_message(&*panic), "panicked: out of range");
+    }
+}
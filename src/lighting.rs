@@ -0,0 +1,81 @@
+use crate::{colour::Colour, config::LightingConfig, renderer::Sample};
+use ndarray::{Array2, Zip};
+
+type Vector = (f64, f64, f64);
+
+/// Lights the samples as a height map with a directional light, points of the set are left flat
+///
+/// Normals come from the central differences of the heights, and are shaded with ambient,
+/// diffuse and Blinn-Phong specular terms on top of the gradient colour. Slopes are multiplied
+/// by the pixel ratio so renders at a higher resolution look the same as the preview.
+pub fn shade(
+    samples: &Array2<Sample>,
+    config: &LightingConfig,
+    pixel_ratio: f64,
+) -> Array2<Colour> {
+    let mut colours = Array2::from_elem(samples.dim(), Colour { r: 0, g: 0, b: 0 });
+    if !config.enabled {
+        Zip::from(&mut colours)
+            .and(samples)
+            .par_apply(|colour, sample| *colour = sample.colour);
+        return colours;
+    }
+
+    let (width, height) = samples.dim();
+    let light = direction(config);
+    let halfway = normalise((light.0, light.1, light.2 + 1.0));
+
+    Zip::indexed(&mut colours).par_apply(|(x, y), colour| {
+        let sample = samples[[x, y]];
+        let h = match sample.height {
+            Some(h) if h.is_finite() => h,
+            _ => {
+                *colour = sample.colour;
+                return;
+            }
+        };
+
+        // Neighbours without a height are treated as level with this pixel
+        let at = |x: usize, y: usize| match samples[[x, y]].height {
+            Some(h) if h.is_finite() => h,
+            _ => h,
+        };
+        let (left, right) = (x.saturating_sub(1), (x + 1).min(width - 1));
+        let (up, down) = (y.saturating_sub(1), (y + 1).min(height - 1));
+        let dx = (at(right, y) - at(left, y)) / (right - left).max(1) as f64;
+        let dy = (at(x, down) - at(x, up)) / (down - up).max(1) as f64;
+        let strength = config.strength * pixel_ratio;
+        let normal = normalise((-dx * strength, -dy * strength, 1.0));
+
+        let diffuse = dot(normal, light).max(0.0);
+        let specular = dot(normal, halfway).max(0.0).powf(config.shininess);
+        let intensity = config.ambient + config.diffuse * diffuse;
+        let highlight = config.specular * specular * 255.0;
+        let channel = |c: u8| (c as f64 * intensity + highlight).clamp(0.0, 255.0) as u8;
+        *colour = Colour {
+            r: channel(sample.colour.r),
+            g: channel(sample.colour.g),
+            b: channel(sample.colour.b),
+        };
+    });
+    colours
+}
+
+fn dot(a: Vector, b: Vector) -> f64 {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+fn normalise(v: Vector) -> Vector {
+    let length = dot(v, v).sqrt();
+    (v.0 / length, v.1 / length, v.2 / length)
+}
+
+/// Unit vector pointing towards the light, in image space with y going down
+fn direction(config: &LightingConfig) -> Vector {
+    let (azimuth, elevation) = (config.azimuth.to_radians(), config.elevation.to_radians());
+    (
+        elevation.cos() * azimuth.cos(),
+        -elevation.cos() * azimuth.sin(),
+        elevation.sin(),
+    )
+}
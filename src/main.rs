@@ -1,24 +1,67 @@
+macro_rules! now {
+    () => {
+        chrono::Local::now().format("%H:%M:%S%.6f")
+    };
+}
+
+mod animation;
+mod batch;
+mod bookmarks;
+mod checkpoint;
 mod colour;
 mod config;
+mod double_double;
+mod encoder;
+mod exponential_map;
+mod expr;
+mod fixed;
+mod formula;
+mod history;
+mod hud;
+mod jobs;
+mod lighting;
 mod mandelbrot;
-
-use self::{colour::Colour, config::Config};
+mod metadata;
+mod perturbation;
+mod preview;
+mod renderer;
+mod trap;
+mod view;
+
+use self::{
+    checkpoint::{Cancelled, Rows},
+    colour::Colour,
+    config::{Config, Resolution},
+    fixed::Fixed,
+    history::History,
+    hud::Hud,
+    jobs::{Progress, RenderQueue},
+    preview::PreviewWorker,
+    renderer::{Precision, Renderer, Sample},
+    view::View,
+};
 use anyhow::{Error, Result};
 use chrono::Local;
 use ndarray::{Array2, Zip};
 use notify::{RecursiveMode, Watcher};
-use num_complex::Complex64;
 use png::{BitDepth, ColorType, Encoder};
 use sdl2::{
     event::{Event, WindowEvent},
-    render::WindowCanvas,
+    keyboard::Mod,
+    mouse::{MouseButton, MouseWheelDirection},
+    pixels::PixelFormatEnum,
+    rect::Rect,
+    render::{Texture, TextureCreator, WindowCanvas},
+    video::WindowContext,
 };
 use std::{
     fs::{self, File},
     io::{BufWriter, Write},
-    path::PathBuf,
-    sync::mpsc::{self, TryRecvError},
-    thread,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, TryRecvError},
+    },
     time::Duration,
 };
 use structopt::StructOpt;
@@ -29,26 +72,189 @@ struct Opt {
     /// File to load the configuration from
     #[structopt(name = "FILE", default_value = "fractal.toml", env = "FRACTAL_CONFIG")]
     config: PathBuf,
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-macro_rules! now {
-    () => {
-        Local::now().format("%H:%M:%S%.6f")
-    };
+#[derive(StructOpt)]
+enum Command {
+    /// Renders a single image without opening the preview window
+    Render(RenderOpt),
+    /// Renders every image listed in a TOML or JSON job file without opening the preview window
+    Batch(BatchOpt),
+    /// Renders a zoom animation as numbered frames in the render directory
+    Animate(AnimateOpt),
+    /// Renders an animation described by a TOML or JSON keyframe file as numbered frames
+    Keyframes(KeyframesOpt),
+    /// Renders a log-polar strip around the centre of the view, covering many zoom octaves
+    ExpMap(ExpMapOpt),
+    /// Rebuilds the frames of a zoom into the centre of the view from a log-polar strip
+    ExpMapFrames(ExpMapFramesOpt),
+    /// Opens the preview on the view and configuration stored in a rendered image
+    Open(OpenOpt),
+    /// Finishes a render saved when the preview was closed, from the rows it completed
+    Resume(ResumeOpt),
+}
+
+#[derive(StructOpt)]
+struct RenderOpt {
+    /// Centre of the view as "re,im", overrides the configured centre
+    #[structopt(long, allow_hyphen_values = true, parse(try_from_str = parse_center))]
+    center: Option<[Fixed; 2]>,
+    /// Zoom relative to the default view, overrides the configured zoom
+    #[structopt(long, parse(try_from_str = parse_zoom))]
+    zoom: Option<f64>,
+    /// Horizontal pixels in the image, overrides the configured render width
+    #[structopt(long)]
+    width: Option<usize>,
+    /// Vertical pixels in the image, overrides the configured render height
+    #[structopt(long)]
+    height: Option<usize>,
+    /// Path of the image, defaults to a timestamped file in the render directory
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct AnimateOpt {
+    /// Number of frames, overrides the configured count
+    #[structopt(long)]
+    frames: Option<usize>,
+    /// Centre of the last frame as "re,im", overrides the configured target
+    #[structopt(long, allow_hyphen_values = true, parse(try_from_str = parse_center))]
+    center: Option<[Fixed; 2]>,
+    /// Zoom of the last frame relative to the default view, overrides the configured zoom
+    #[structopt(long, parse(try_from_str = parse_zoom))]
+    zoom: Option<f64>,
+}
+
+#[derive(StructOpt)]
+struct KeyframesOpt {
+    /// Keyframe file, read as JSON if it has a .json extension and TOML otherwise
+    #[structopt(name = "KEYFRAMES")]
+    keyframes: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct ExpMapOpt {
+    /// Path of the strip, defaults to a timestamped file in the render directory
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct ExpMapFramesOpt {
+    /// Strip rendered with the same configuration
+    #[structopt(name = "STRIP")]
+    strip: PathBuf,
+    /// Number of frames, overrides the configured animation frame count
+    #[structopt(long)]
+    frames: Option<usize>,
+}
+
+#[derive(StructOpt)]
+struct OpenOpt {
+    /// Image rendered with the preview, the render or the batch command
+    #[structopt(name = "IMAGE")]
+    image: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct ResumeOpt {
+    /// Checkpoint saved next to where the image would have been
+    #[structopt(name = "CHECKPOINT")]
+    checkpoint: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct BatchOpt {
+    /// Job file, read as JSON if it has a .json extension and TOML otherwise
+    #[structopt(name = "JOBS")]
+    jobs: PathBuf,
+    /// Number of jobs rendered at the same time, overrides the job file
+    #[structopt(long)]
+    concurrency: Option<usize>,
 }
 
 #[paw::main]
 fn main(args: Opt) -> Result<()> {
-    let (mut config, config_rx, _w) = if fs::metadata(&args.config).is_ok() {
+    match args.command {
+        Some(Command::Render(opt)) => render_headless(&args.config, opt),
+        Some(Command::Batch(opt)) => {
+            let config = read_config(&args.config)?;
+            let mut jobs = self::config::read_data::<self::batch::JobFile>(&opt.jobs)?;
+            if let Some(concurrency) = opt.concurrency {
+                jobs.concurrency = concurrency;
+            }
+            self::batch::run(jobs, &config)
+        }
+        Some(Command::Animate(opt)) => {
+            let mut config = read_config(&args.config)?;
+            if let Some(frames) = opt.frames {
+                config.animation.frames = frames;
+            }
+            if let Some(center) = opt.center {
+                config.animation.center = Some(center);
+            }
+            if let Some(zoom) = opt.zoom {
+                config.animation.zoom = zoom;
+            }
+            self::animation::zoom(&config)
+        }
+        Some(Command::Keyframes(opt)) => {
+            let config = read_config(&args.config)?;
+            let animation = self::config::read_data(&opt.keyframes)?;
+            self::animation::keyframes(&animation, &config)
+        }
+        Some(Command::ExpMap(opt)) => {
+            let config = read_config(&args.config)?;
+            let filepath = opt.output.unwrap_or_else(|| timestamped_path(&config));
+            println!(
+                "[{}] [RENDER] Started rendering the exponential map, {} octaves",
+                now!(),
+                config.exponential_map.octaves
+            );
+            let (p, precision) = self::exponential_map::render(&config, filepath)?;
+            println!(
+                "[{}] [RENDER] Done rendering {} using {} precision",
+                now!(),
+                p.display(),
+                precision
+            );
+            Ok(())
+        }
+        Some(Command::ExpMapFrames(opt)) => {
+            let mut config = read_config(&args.config)?;
+            if let Some(frames) = opt.frames {
+                config.animation.frames = frames;
+            }
+            self::exponential_map::frames(&config, &opt.strip)
+        }
+        Some(Command::Open(opt)) => run(&args.config, Some(&opt.image)),
+        Some(Command::Resume(opt)) => resume(&opt.checkpoint),
+        None => run(&args.config, None),
+    }
+}
+
+/// Opens the preview, on the view stored in an image if one is given
+fn run(config_path: &Path, image: Option<&Path>) -> Result<()> {
+    let (mut config, config_rx, _w) = if let Some(image) = image {
+        println!(
+            "[{}] [CONFIG] Using {}, refreshing disabled",
+            now!(),
+            image.display()
+        );
+        (self::metadata::read(image)?, None, None)
+    } else if fs::metadata(config_path).is_ok() {
         println!(
             "[{}] [CONFIG] Using {}, refreshing enabled",
             now!(),
-            args.config.display()
+            config_path.display()
         );
-        let c = self::config::read(&args.config)?;
+        let c = self::config::read(config_path)?;
         let (tx, rx) = mpsc::channel();
         let mut watcher = notify::watcher(tx, Duration::from_secs(2))?;
-        watcher.watch(&args.config, RecursiveMode::NonRecursive)?;
+        watcher.watch(config_path, RecursiveMode::NonRecursive)?;
         (c, Some(rx), Some(watcher))
     } else {
         println!("[{}] [CONFIG] Using default, refreshing disabled", now!());
@@ -73,49 +279,58 @@ fn main(args: Opt) -> Result<()> {
         config.preview.resolution.width as _,
         config.preview.resolution.height as _,
     )?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = preview_texture(&texture_creator, config.preview.resolution)?;
     let mut events = ctx.event_pump().map_err(Error::msg)?;
 
-    let (mut fractal_dimensions, mut fractal_offsets) = {
-        if config.preview.resolution.width as f64 / config.preview.resolution.height as f64
-            >= 3.5 / 2.0
-        {
-            let width = 2.0 * config.preview.resolution.width as f64
-                / config.preview.resolution.height as f64;
-            ((width, 2.0), (-2.5 + (3.5 - width) / 2.0, -1.0))
-        } else {
-            let height = 3.5 * config.preview.resolution.height as f64
-                / config.preview.resolution.width as f64;
-            ((3.5, height), (-2.5, -1.0 + (1.0 - height) / 2.0))
-        }
-    };
-    let mut scale_factor = fractal_dimensions.0 / config.preview.resolution.width as f64;
-
-    preview(scale_factor, fractal_offsets, &mut canvas, &config)?;
+    let mut view = View::initial(&config);
+    let mut history = History::default();
+    // Index of the last bookmark gone to, cycling starts from the first one
+    let mut bookmark = None;
+
+    let mut worker = PreviewWorker::new();
+    worker.request(&view, &config);
+    // Last position of the cursor in the preview, which the wheel zooms around
+    let mut cursor = (
+        config.preview.resolution.width as i32 / 2,
+        config.preview.resolution.height as i32 / 2,
+    );
+    let mut drag: Option<Drag> = None;
+    let mut hud = Hud::default();
+    let mut jobs = RenderQueue::new(config.render.concurrency, config.render.on_quit);
     loop {
+        // Whether the preview has to be drawn again even if the HUD is hidden
+        let mut redraw = false;
         if let Some(rx) = &config_rx {
             match rx.try_recv() {
-                Ok(_) => match self::config::read(&args.config) {
+                Ok(_) => match self::config::read(config_path) {
                     Ok(c) => {
                         eprintln!("[{}] [CONFIG] Refreshed", now!());
 
-                        scale_factor = scale(
-                            scale_factor,
-                            (
-                                config.preview.resolution.width as f64,
-                                config.preview.resolution.height as f64,
-                            ),
-                            (
-                                c.preview.resolution.width as f64,
-                                c.preview.resolution.height as f64,
-                            ),
-                        );
+                        if c.fractal != config.fractal
+                            || c.formula != config.formula
+                            || c.multibrot_exponent != config.multibrot_exponent
+                            || c.view != config.view
+                        {
+                            history.push(&view, c.preview.history);
+                            view = View::initial(&c);
+                        } else {
+                            let (old, new) = (preview_size(&config), preview_size(&c));
+                            view.scale_factor = scale(view.scale_factor, old, new);
+                            for v in history.views_mut() {
+                                v.scale_factor = scale(v.scale_factor, old, new);
+                            }
+                        }
                         config = c;
+                        jobs.on_quit = config.render.on_quit;
+                        jobs.resize(config.render.concurrency);
                         canvas.set_logical_size(
                             config.preview.resolution.width as _,
                             config.preview.resolution.height as _,
                         )?;
+                        texture = preview_texture(&texture_creator, config.preview.resolution)?;
 
-                        preview(scale_factor, fractal_offsets, &mut canvas, &config)?;
+                        worker.reload(&view, &config);
                     }
                     Err(e) => eprintln!("[{}] [CONFIG] [ERROR] {}", now!(), e),
                 },
@@ -124,7 +339,15 @@ fn main(args: Opt) -> Result<()> {
             }
         }
 
-        match events.wait_event_timeout(2000) {
+        // Passes of the preview are waited for alongside the events, and renders report often
+        let timeout = if worker.busy() {
+            10
+        } else if jobs.busy() {
+            250
+        } else {
+            2000
+        };
+        match events.wait_event_timeout(timeout) {
             Some(Event::Quit { .. }) => break,
 
             Some(Event::Window {
@@ -134,116 +357,308 @@ fn main(args: Opt) -> Result<()> {
             | Some(Event::Window {
                 win_event: WindowEvent::SizeChanged(_, _),
                 ..
-            }) => preview(scale_factor, fractal_offsets, &mut canvas, &config)?,
+            }) => redraw = true,
 
             Some(Event::KeyUp {
                 keycode: Some(k), ..
             }) if config.preview.keys.zoom_in == k => {
-                let old_center = (
-                    fractal_dimensions.0 / 2.0 + fractal_offsets.0,
-                    fractal_dimensions.1 / 2.0 + fractal_offsets.1,
-                );
-
-                fractal_dimensions.0 /= config.preview.zoom_factor;
-                fractal_dimensions.1 /= config.preview.zoom_factor;
-
-                let new_center = (fractal_dimensions.0 / 2.0, fractal_dimensions.1 / 2.0);
-                fractal_offsets.0 = old_center.0 - new_center.0;
-                fractal_offsets.1 = old_center.1 - new_center.1;
-
-                scale_factor = fractal_dimensions.0 / config.preview.resolution.width as f64;
-                preview(scale_factor, fractal_offsets, &mut canvas, &config)?;
+                history.push(&view, config.preview.history);
+                view.zoom(config.preview.zoom_factor);
+                worker.request(&view, &config);
             }
             Some(Event::KeyUp {
                 keycode: Some(k), ..
             }) if config.preview.keys.zoom_out == k => {
-                let old_center = (
-                    fractal_dimensions.0 / 2.0 + fractal_offsets.0,
-                    fractal_dimensions.1 / 2.0 + fractal_offsets.1,
-                );
-
-                fractal_dimensions.0 *= config.preview.zoom_factor;
-                fractal_dimensions.1 *= config.preview.zoom_factor;
-
-                let new_center = (fractal_dimensions.0 / 2.0, fractal_dimensions.1 / 2.0);
-                fractal_offsets.0 = old_center.0 - new_center.0;
-                fractal_offsets.1 = old_center.1 - new_center.1;
-
-                scale_factor = fractal_dimensions.0 / config.preview.resolution.width as f64;
-                preview(scale_factor, fractal_offsets, &mut canvas, &config)?;
+                history.push(&view, config.preview.history);
+                view.zoom(1.0 / config.preview.zoom_factor);
+                worker.request(&view, &config);
             }
 
             Some(Event::KeyUp {
                 keycode: Some(k), ..
             }) if config.preview.keys.up == k => {
-                fractal_offsets.1 -= fractal_dimensions.1 * config.preview.move_factor;
-                preview(scale_factor, fractal_offsets, &mut canvas, &config)?;
+                history.push(&view, config.preview.history);
+                let step = move_step(&view, &config);
+                view.translate(0.0, -step.1);
+                worker.request(&view, &config);
             }
             Some(Event::KeyUp {
                 keycode: Some(k), ..
             }) if config.preview.keys.left == k => {
-                fractal_offsets.0 -= fractal_dimensions.0 * config.preview.move_factor;
-                preview(scale_factor, fractal_offsets, &mut canvas, &config)?;
+                history.push(&view, config.preview.history);
+                let step = move_step(&view, &config);
+                view.translate(-step.0, 0.0);
+                worker.request(&view, &config);
             }
             Some(Event::KeyUp {
                 keycode: Some(k), ..
             }) if config.preview.keys.down == k => {
-                fractal_offsets.1 += fractal_dimensions.1 * config.preview.move_factor;
-                preview(scale_factor, fractal_offsets, &mut canvas, &config)?;
+                history.push(&view, config.preview.history);
+                let step = move_step(&view, &config);
+                view.translate(0.0, step.1);
+                worker.request(&view, &config);
             }
             Some(Event::KeyUp {
                 keycode: Some(k), ..
             }) if config.preview.keys.right == k => {
-                fractal_offsets.0 += fractal_dimensions.0 * config.preview.move_factor;
-                preview(scale_factor, fractal_offsets, &mut canvas, &config)?;
+                history.push(&view, config.preview.history);
+                let step = move_step(&view, &config);
+                view.translate(step.0, 0.0);
+                worker.request(&view, &config);
             }
 
             Some(Event::KeyUp {
                 keycode: Some(k), ..
-            }) if config.preview.keys.render == k => {
-                render(scale_factor, fractal_offsets, config.clone())
+            }) if config.preview.keys.render == k => render(view.clone(), config.clone(), &jobs),
+
+            Some(Event::KeyUp {
+                keycode: Some(k), ..
+            }) if config.preview.keys.cancel_render == k => jobs.cancel_last(),
+
+            Some(Event::KeyUp {
+                keycode: Some(k), ..
+            }) if config.preview.keys.hud == k => {
+                hud.visible = !hud.visible;
+                redraw = true;
             }
 
-            Some(Event::MouseButtonUp { x, y, .. }) => {
-                let x = x as f64 * scale_factor + fractal_offsets.0;
-                let y = y as f64 * scale_factor + fractal_offsets.1;
-                println!("[{}] [COORDS] ({}, {})", now!(), x, y);
+            Some(Event::KeyUp {
+                keycode: Some(k), ..
+            }) if config.preview.keys.undo == k => match history.undo(&view) {
+                Some(v) => {
+                    view = v;
+                    worker.request(&view, &config);
+                }
+                None => println!("[{}] [HISTORY] Nothing to undo", now!()),
+            },
+            Some(Event::KeyUp {
+                keycode: Some(k), ..
+            }) if config.preview.keys.redo == k => match history.redo(&view) {
+                Some(v) => {
+                    view = v;
+                    worker.request(&view, &config);
+                }
+                None => println!("[{}] [HISTORY] Nothing to redo", now!()),
+            },
+
+            Some(Event::KeyUp {
+                keycode: Some(k), ..
+            }) if config.preview.keys.bookmark == k => {
+                let path = &config.preview.bookmarks;
+                match self::bookmarks::save(path, &view, &config) {
+                    Ok(b) => println!(
+                        "[{}] [BOOKMARK] Saved \"{}\" to {}",
+                        now!(),
+                        b.name,
+                        path.display()
+                    ),
+                    Err(e) => eprintln!("[{}] [BOOKMARK] [ERROR] {}", now!(), e),
+                }
+            }
+            Some(Event::KeyUp {
+                keycode: Some(k), ..
+            }) if config.preview.keys.next_bookmark == k
+                || config.preview.keys.previous_bookmark == k =>
+            {
+                // Read every time so bookmarks edited by hand are picked up
+                let bookmarks = match self::bookmarks::read(&config.preview.bookmarks) {
+                    Ok(bookmarks) if !bookmarks.is_empty() => bookmarks,
+                    Ok(_) => {
+                        println!(
+                            "[{}] [BOOKMARK] No bookmarks in {}",
+                            now!(),
+                            config.preview.bookmarks.display()
+                        );
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("[{}] [BOOKMARK] [ERROR] {}", now!(), e);
+                        continue;
+                    }
+                };
+                let len = bookmarks.len();
+                let i = if config.preview.keys.next_bookmark == k {
+                    bookmark.map_or(0, |i| (i + 1) % len)
+                } else {
+                    bookmark.map_or(len - 1, |i| (i + len - 1) % len)
+                };
+                bookmark = Some(i);
+                println!(
+                    "[{}] [BOOKMARK] [{}/{}] Going to \"{}\"",
+                    now!(),
+                    i + 1,
+                    len,
+                    bookmarks[i].name
+                );
+                history.push(&view, config.preview.history);
+                view = bookmarks[i].view(&config);
+                worker.request(&view, &config);
+            }
+
+            Some(Event::MouseButtonDown {
+                mouse_btn, x, y, ..
+            }) => {
+                drag = Some(Drag {
+                    button: mouse_btn,
+                    keymod: ctx.keyboard().mod_state(),
+                    start: (x, y),
+                    end: (x, y),
+                    moved: false,
+                })
+            }
+            Some(Event::MouseMotion { x, y, .. }) => {
+                cursor = (x, y);
+                if let Some(d) = &mut drag {
+                    d.end = (x, y);
+                    d.moved |= (x - d.start.0).abs().max((y - d.start.1).abs()) > DRAG_THRESHOLD;
+                    if d.moved && config.preview.mouse.box_zoom.button(d.button, d.keymod) {
+                        draw(&mut canvas, &texture, (0, 0))?;
+                        canvas.set_draw_color((0xff, 0xff, 0xff));
+                        canvas.draw_rect(d.rect()).map_err(Error::msg)?;
+                        hud.draw(&mut canvas, &view, &config, cursor)?;
+                        canvas.present();
+                    } else if d.moved && config.preview.mouse.pan.button(d.button, d.keymod) {
+                        // The view is only rendered again once the button is released
+                        let offset = (d.end.0 - d.start.0, d.end.1 - d.start.1);
+                        draw(&mut canvas, &texture, offset)?;
+                        hud.draw(&mut canvas, &view, &config, cursor)?;
+                        canvas.present();
+                    }
+                }
+            }
+            Some(Event::MouseButtonUp {
+                mouse_btn, x, y, ..
+            }) => match drag.take() {
+                Some(d) if d.button == mouse_btn => {
+                    let mouse = &config.preview.mouse;
+                    let resolution = config.preview.resolution;
+                    if d.moved && mouse.box_zoom.button(d.button, d.keymod) {
+                        history.push(&view, config.preview.history);
+                        let start = (d.start.0 as f64, d.start.1 as f64);
+                        view.zoom_box(start, (x as f64, y as f64), resolution);
+                        worker.request(&view, &config);
+                    } else if d.moved && mouse.pan.button(d.button, d.keymod) {
+                        history.push(&view, config.preview.history);
+                        view.translate(
+                            -(x - d.start.0) as f64 * view.scale_factor,
+                            -(y - d.start.1) as f64 * view.scale_factor,
+                        );
+                        worker.request(&view, &config);
+                    } else if !d.moved && mouse.coordinates.button(d.button, d.keymod) {
+                        let (x, y) = view.point(x as f64, y as f64, resolution);
+                        let digits = view.digits();
+                        println!("[{}] [COORDS] ({:.*}, {:.*})", now!(), digits, x, digits, y);
+                    }
+                }
+                other => drag = other,
+            },
+            Some(Event::MouseWheel { y, direction, .. })
+                if config.preview.mouse.zoom.wheel(ctx.keyboard().mod_state()) =>
+            {
+                let notches = match direction {
+                    MouseWheelDirection::Flipped => -y,
+                    _ => y,
+                };
+                if notches != 0 {
+                    history.push(&view, config.preview.history);
+                    view.zoom_at(
+                        config.preview.zoom_factor.powi(notches),
+                        cursor.0 as f64,
+                        cursor.1 as f64,
+                        config.preview.resolution,
+                    );
+                    worker.request(&view, &config);
+                }
             }
 
             _ => (),
         }
+
+        if let Some(frame) = worker.poll() {
+            texture.update(None, &frame.pixels, frame.width * 3)?;
+            hud.block = frame.block;
+            if frame.block == 1 {
+                hud.preview_time = Some(frame.elapsed);
+            }
+            redraw = true;
+        }
+        hud.busy = worker.busy();
+        jobs.report();
+        hud.renders = jobs.status();
+
+        // Drags draw the moved preview themselves
+        if (redraw || hud.visible) && !drag.as_ref().is_some_and(|d| d.moved) {
+            draw(&mut canvas, &texture, (0, 0))?;
+            hud.draw(&mut canvas, &view, &config, cursor)?;
+            canvas.present();
+        }
     }
 
+    // Unfinished renders outlive the window, they are dealt with when the queue is dropped
+    canvas.window_mut().hide();
+    drop(jobs);
+
     Ok(())
 }
 
-fn preview(
-    scale_factor: f64,
-    offsets: (f64, f64),
-    canvas: &mut WindowCanvas,
-    config: &Config,
-) -> Result<()> {
-    let (x, y) = (
-        config.preview.resolution.width,
-        config.preview.resolution.height,
-    );
-    for x in 0..(x as i32) {
-        for y in 0..(y as i32) {
-            let c = Complex64::new(
-                x as f64 * scale_factor + offsets.0,
-                y as f64 * scale_factor + offsets.1,
-            );
-            let colour = self::mandelbrot::colourise(
-                c,
-                config.max_iterations,
-                &config.gradient,
-                config.black,
-            );
-            canvas.set_draw_color((colour.r, colour.g, colour.b));
-            canvas.draw_point((x, y)).map_err(Error::msg)?;
-        }
+/// Mouse button held down in the preview
+struct Drag {
+    button: MouseButton,
+    /// Modifier keys held when the button was pressed, which pick the action
+    keymod: Mod,
+    start: (i32, i32),
+    end: (i32, i32),
+    /// Whether the cursor went further than a click would
+    moved: bool,
+}
+
+impl Drag {
+    fn rect(&self) -> Rect {
+        Rect::new(
+            self.start.0.min(self.end.0),
+            self.start.1.min(self.end.1),
+            (self.end.0 - self.start.0).unsigned_abs(),
+            (self.end.1 - self.start.1).unsigned_abs(),
+        )
+    }
+}
+
+/// Pixels the cursor can move while a button is held before it counts as a drag
+const DRAG_THRESHOLD: i32 = 2;
+
+/// Distance moved by the movement keys, in whole pixels so the preview can keep the others
+fn move_step(view: &View, config: &Config) -> (f64, f64) {
+    let pixels = |size: usize| (size as f64 * config.preview.move_factor).round();
+    (
+        pixels(config.preview.resolution.width) * view.scale_factor,
+        pixels(config.preview.resolution.height) * view.scale_factor,
+    )
+}
+
+/// Streaming texture holding the last frame of the preview
+fn preview_texture(
+    texture_creator: &TextureCreator<WindowContext>,
+    resolution: Resolution,
+) -> Result<Texture<'_>> {
+    let texture = texture_creator.create_texture_streaming(
+        PixelFormatEnum::RGB24,
+        resolution.width as _,
+        resolution.height as _,
+    )?;
+    Ok(texture)
+}
+
+/// Draws the last frame of the preview moved by an offset, what it doesn't cover is left black
+fn draw(canvas: &mut WindowCanvas, texture: &Texture, offset: (i32, i32)) -> Result<()> {
+    if offset != (0, 0) {
+        canvas.set_draw_color((0, 0, 0));
+        canvas.clear();
     }
-    canvas.present();
+    let query = texture.query();
+    let destination = Rect::new(offset.0, offset.1, query.width, query.height);
+    canvas
+        .copy(texture, None, destination)
+        .map_err(Error::msg)?;
     Ok(())
 }
 
@@ -256,56 +671,248 @@ fn scale(mut factor: f64, old: (f64, f64), new: (f64, f64)) -> f64 {
     factor
 }
 
-fn render(scale_factor: f64, offsets: (f64, f64), config: Config) {
-    println!("[{}] [RENDER] Started rendering", now!());
-    thread::spawn(move || match render_inner(scale_factor, offsets, config) {
-        Ok(p) => println!("[{}] [RENDER] Done rendering {}", now!(), p.display()),
-        Err(e) => eprintln!("[{}] [RENDER] [ERROR] {}", now!(), e),
-    });
+/// Queues a render of the view, run in the background once a worker is free
+fn render(view: View, config: Config, jobs: &RenderQueue) {
+    let digits = view.digits();
+    println!(
+        "[{}] [RENDER] Queueing center = [\"{:.*}\", \"{:.*}\"], zoom = {:e}",
+        now!(),
+        digits,
+        view.center.0,
+        digits,
+        view.center.1,
+        view.zoom_level(&config)
+    );
+    let filepath = timestamped_path(&config);
+    let view = render_view(view, &config);
+    jobs.push(view, config, filepath);
+}
+
+/// Renders a single image synchronously, SDL is never initialised
+fn render_headless(config_path: &Path, opt: RenderOpt) -> Result<()> {
+    let mut config = read_config(config_path)?;
+    if let Some(center) = opt.center {
+        config.view.center = Some(center);
+    }
+    if let Some(zoom) = opt.zoom {
+        config.view.zoom = zoom;
+    }
+    if let Some(width) = opt.width {
+        config.render.resolution.width = width;
+    }
+    if let Some(height) = opt.height {
+        config.render.resolution.height = height;
+    }
+
+    let view = View::initial(&config);
+    let filepath = opt.output.unwrap_or_else(|| timestamped_path(&config));
+    let digits = view.digits();
+    println!(
+        "[{}] [RENDER] Started rendering center = [\"{:.*}\", \"{:.*}\"], zoom = {:e}",
+        now!(),
+        digits,
+        view.center.0,
+        digits,
+        view.center.1,
+        view.zoom_level(&config)
+    );
+    let view = render_view(view, &config);
+    let (p, precision) = render_inner(view, config, filepath, None, None)?;
+    println!(
+        "[{}] [RENDER] Done rendering {} using {} precision",
+        now!(),
+        p.display(),
+        precision
+    );
+    Ok(())
 }
 
-fn render_inner(mut scale_factor: f64, offsets: (f64, f64), config: Config) -> Result<PathBuf> {
-    scale_factor = scale(
-        scale_factor,
-        (
-            config.preview.resolution.width as f64,
-            config.preview.resolution.height as f64,
-        ),
-        (
-            config.render.resolution.width as f64,
-            config.render.resolution.height as f64,
-        ),
+/// Finishes a render from a checkpoint, which is removed once the image is written
+fn resume(path: &Path) -> Result<()> {
+    let (configuration, rows) = self::checkpoint::load(path)?;
+    let config = self::config::from_table(toml::from_str(&configuration)?)?;
+    let resolution = config.render.resolution;
+    if rows.samples.dim() != (resolution.width, resolution.height) {
+        return Err(Error::msg("the checkpoint doesn't match its configuration"));
+    }
+
+    // The checkpoint is named after the image
+    let filepath = path.with_extension("");
+    println!(
+        "[{}] [RENDER] Resuming {} from {} of {} rows",
+        now!(),
+        filepath.display(),
+        rows.count(),
+        resolution.height
+    );
+    let view = render_view(View::initial(&config), &config);
+    let (p, precision) = render_inner(view, config, filepath, None, Some(rows))?;
+    fs::remove_file(path)?;
+    println!(
+        "[{}] [RENDER] Done rendering {} using {} precision",
+        now!(),
+        p.display(),
+        precision
     );
+    Ok(())
+}
+
+/// Reads the configuration once, for the commands which don't open the preview window
+fn read_config(path: &Path) -> Result<Config> {
+    if fs::metadata(path).is_ok() {
+        println!("[{}] [CONFIG] Using {}", now!(), path.display());
+        self::config::read(path)
+    } else {
+        println!("[{}] [CONFIG] Using default", now!());
+        Ok(Config::default())
+    }
+}
 
+fn parse_center(value: &str) -> Result<[Fixed; 2]> {
+    let mut parts = value.splitn(2, ',');
+    match (parts.next(), parts.next()) {
+        (Some(re), Some(im)) => Ok([Fixed::parse(re.trim())?, Fixed::parse(im.trim())?]),
+        _ => Err(Error::msg("expected the centre as re,im")),
+    }
+}
+
+fn parse_zoom(value: &str) -> Result<f64> {
+    let zoom: f64 = value.parse()?;
+    if zoom > 0.0 && zoom.is_finite() {
+        Ok(zoom)
+    } else {
+        Err(Error::msg("expected a positive number"))
+    }
+}
+
+fn timestamped_path(config: &Config) -> PathBuf {
     let timestamp = Local::now();
     let filename = format!("{}.png", timestamp.format("%Y-%m-%d_%H-%M-%S"));
-    let filepath = config.render.directory.join(filename);
+    config.render.directory.join(filename)
+}
 
-    fs::create_dir_all(&config.render.directory)?;
+/// Scales a view of the preview so the render shows the same area
+fn render_view(mut view: View, config: &Config) -> View {
+    view.scale_factor = scale(view.scale_factor, preview_size(config), render_size(config));
+    view
+}
 
-    let (width, height) = (
-        config.render.resolution.width,
-        config.render.resolution.height,
-    );
-    let mut matrix: Array2<Colour> =
-        Array2::from_elem((width, height), Colour { r: 0, g: 0, b: 0 });
-    Zip::indexed(&mut matrix).par_apply(|(x, y), colour| {
-        let c = Complex64::new(
-            x as f64 * scale_factor + offsets.0,
-            y as f64 * scale_factor + offsets.1,
-        );
-        *colour =
-            self::mandelbrot::colourise(c, config.max_iterations, &config.gradient, config.black);
+fn preview_size(config: &Config) -> (f64, f64) {
+    (
+        config.preview.resolution.width as f64,
+        config.preview.resolution.height as f64,
+    )
+}
+
+fn render_size(config: &Config) -> (f64, f64) {
+    (
+        config.render.resolution.width as f64,
+        config.render.resolution.height as f64,
+    )
+}
+
+/// Renders a view already scaled to the render resolution, keeping the rows already sampled
+fn render_inner(
+    view: View,
+    config: Config,
+    filepath: PathBuf,
+    progress: Option<&Progress>,
+    rows: Option<Rows>,
+) -> Result<(PathBuf, Precision)> {
+    let (matrix, precision) = render_matrix(&view, &config, progress, rows)?;
+    let metadata = self::metadata::text_chunks(&view, &config)?;
+    write_png(&filepath, &matrix, &metadata)?;
+    Ok((filepath, precision))
+}
+
+/// Colours of a view already scaled to the render resolution
+fn render_matrix(
+    view: &View,
+    config: &Config,
+    progress: Option<&Progress>,
+    rows: Option<Rows>,
+) -> Result<(Array2<Colour>, Precision)> {
+    // Lighting slopes are relative to the pixels of the preview
+    let pixel_ratio = 1.0 / scale(1.0, preview_size(config), render_size(config));
+
+    let renderer = Renderer::new(view, config.render.resolution, config);
+    let samples = sample_all(&renderer, config.render.resolution, progress, rows)?;
+    let matrix = self::lighting::shade(&samples, &config.lighting, pixel_ratio);
+    Ok((matrix, renderer.precision()))
+}
+
+/// Samples every pixel of the renderer in parallel, counting them and stopping once cancelled
+///
+/// Complete rows from an earlier render of the view are kept. Cancelled renders fail with a
+/// [`Cancelled`] error holding the rows they completed, so they can be resumed from.
+fn sample_all(
+    renderer: &Renderer,
+    resolution: Resolution,
+    progress: Option<&Progress>,
+    rows: Option<Rows>,
+) -> Result<Array2<Sample>> {
+    let Rows {
+        mut samples,
+        complete,
+    } = rows.unwrap_or_else(|| Rows::new(resolution));
+    if let Some(progress) = progress {
+        let remaining = complete.iter().filter(|&&complete| !complete).count();
+        progress.start(remaining * resolution.width);
+    }
+    // Samples taken in each row, which is complete once it has all of them
+    let taken: Vec<_> = complete
+        .iter()
+        .map(|&complete| AtomicUsize::new(if complete { resolution.width } else { 0 }))
+        .collect();
+    Zip::indexed(&mut samples).par_apply(|(x, y), sample| {
+        if complete[y] || progress.is_some_and(Progress::cancelled) {
+            return;
+        }
+        *sample = renderer.sample(x, y);
+        taken[y].fetch_add(1, Ordering::Relaxed);
+        if let Some(progress) = progress {
+            progress.advance();
+        }
     });
+    if progress.is_some_and(Progress::cancelled) {
+        let complete = taken
+            .iter()
+            .map(|taken| taken.load(Ordering::Relaxed) == resolution.width)
+            .collect();
+        return Err(Error::new(Cancelled(Rows { samples, complete })));
+    }
+    Ok(samples)
+}
+
+/// Writes an image with the given text chunks, keywords should be ASCII
+///
+/// The image only gets its name once complete, so an interrupted write never leaves a partial
+/// image behind it.
+fn write_png(filepath: &Path, matrix: &Array2<Colour>, text: &[(String, String)]) -> Result<()> {
+    if let Some(directory) = filepath.parent() {
+        fs::create_dir_all(directory)?;
+    }
 
+    let mut partial = filepath.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    let (width, height) = matrix.dim();
     let mut encoder = Encoder::new(
-        BufWriter::new(File::create(&filepath)?),
+        BufWriter::new(File::create(&partial)?),
         width as _,
         height as _,
     );
-    encoder.set_color(ColorType::RGB);
+    encoder.set_color(ColorType::Rgb);
     encoder.set_depth(BitDepth::Eight);
-    let mut writer = encoder.write_header()?.into_stream_writer();
+    for (keyword, text) in text {
+        // tEXt chunks are Latin-1, anything outside of ASCII goes in a UTF-8 iTXt chunk
+        if text.is_ascii() {
+            encoder.add_text_chunk(keyword.clone(), text.clone())?;
+        } else {
+            encoder.add_itxt_chunk(keyword.clone(), text.clone())?;
+        }
+    }
+    let mut writer = encoder.write_header()?.into_stream_writer()?;
 
     for y in 0..height {
         for x in 0..width {
@@ -315,5 +922,6 @@ fn render_inner(mut scale_factor: f64, offsets: (f64, f64), config: Config) -> R
     }
 
     writer.finish()?;
-    Ok(filepath)
+    fs::rename(&partial, filepath)?;
+    Ok(())
 }
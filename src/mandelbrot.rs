@@ -1,6 +1,11 @@
-use crate::colour::{Colour, Gradient};
+use crate::{
+    colour::Colour,
+    config::{ColouringMode, Config, Formula, Fractal, InteriorMode, OrbitTrapConfig, TrapShape},
+    double_double::DoubleDouble,
+    formula::EscapeTimeFractal,
+};
 use num_complex::Complex64;
-use num_traits::Zero;
+use num_traits::{One, Zero};
 
 #[derive(Copy, Clone)]
 pub struct Area {
@@ -10,32 +15,453 @@ pub struct Area {
     pub y_end: f64,
 }
 
-fn f(c: Complex64, z: Complex64) -> Complex64 {
-    z * z + c
+/// Squared escape radius used for distance estimation
+const DISTANCE_BAILOUT: f64 = 1e10;
+
+/// Brent's cycle detection, used to stop iterating points of the set as soon as their orbit
+/// becomes periodic
+pub struct Periodicity<T> {
+    check: T,
+    tolerance: f64,
+    power: usize,
+    steps: usize,
+}
+
+impl<T: Copy> Periodicity<T> {
+    /// Takes the orbit for a cycle once z comes back closer than the tolerance times |z|
+    pub fn new(z: T, tolerance: f64) -> Self {
+        Self {
+            check: z,
+            tolerance: tolerance * tolerance,
+            power: 1,
+            steps: 0,
+        }
+    }
+
+    /// Returns the period of the orbit once z comes back to a previously saved value
+    pub fn detect(
+        &mut self,
+        z: T,
+        norm_sqr: f64,
+        distance_sqr: impl Fn(T, T) -> f64,
+    ) -> Option<usize> {
+        self.steps += 1;
+        if distance_sqr(z, self.check) <= self.tolerance * norm_sqr {
+            return Some(self.steps);
+        }
+        if self.steps == self.power {
+            self.check = z;
+            self.power *= 2;
+            self.steps = 0;
+        }
+        None
+    }
+}
+
+/// Period of the attracting cycle if c lies in the main cardioid or the period-2 bulb of the
+/// Mandelbrot set
+fn cardioid_or_bulb_period(c: Complex64) -> Option<usize> {
+    let x = c.re - 0.25;
+    let y2 = c.im * c.im;
+    let q = x * x + y2;
+    if q * (q + x) <= y2 / 4.0 {
+        Some(1)
+    } else if (c.re + 1.0) * (c.re + 1.0) + y2 <= 1.0 / 16.0 {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// State of a point's orbit once it escaped or ran out of iterations
+#[derive(Debug, Copy, Clone)]
+pub struct Orbit {
+    pub iterations: usize,
+    pub z: Complex64,
+    /// Derivative of z with respect to the point, only tracked for distance estimation
+    pub derivative: Complex64,
+    /// Smallest distance from the orbit to the orbit trap, only tracked if it's enabled
+    pub trap: f64,
+    /// Period of the cycle the orbit fell into, if periodicity checking found one
+    pub period: Option<usize>,
+    /// Iteration at which |z| was the smallest, only tracked for atom domain colouring
+    pub atom_domain: usize,
+    min_norm_sqr: f64,
+    /// Interior distance estimate, only computed for points of the Mandelbrot set in f64
+    pub interior_distance: Option<f64>,
 }
 
-fn diverge_iterations(c: Complex64, max: usize) -> (usize, Complex64) {
-    let mut z = Complex64::zero();
-    let mut i = 0;
-    while z.norm_sqr() <= 4.0 && i < max {
-        z = f(c, z);
-        i += 1;
+/// What to track while iterating, besides the escape time
+#[derive(Debug, Copy, Clone)]
+pub struct Tracking {
+    /// Tolerance relative to |z| used for periodicity checking, if enabled
+    pub tolerance: Option<f64>,
+    /// Squared escape radius, much larger than the formula's when estimating distances
+    pub bailout: f64,
+    pub derivative: bool,
+    pub trap: Option<OrbitTrapConfig>,
+    pub atom_domain: bool,
+    pub julia: bool,
+}
+
+impl Orbit {
+    pub fn new(z: Complex64, tracking: &Tracking) -> Self {
+        Self {
+            iterations: 0,
+            z,
+            derivative: if tracking.julia {
+                Complex64::one()
+            } else {
+                Complex64::zero()
+            },
+            trap: f64::INFINITY,
+            period: None,
+            atom_domain: 0,
+            min_norm_sqr: f64::INFINITY,
+            interior_distance: None,
+        }
+    }
+
+    /// Records the current z in the tracked values that depend on the whole orbit
+    pub fn observe(&mut self, tracking: &Tracking) {
+        if let Some(trap) = &tracking.trap {
+            self.trap = self.trap.min(crate::trap::distance(trap, self.z));
+        }
+        if tracking.atom_domain {
+            let norm_sqr = self.z.norm_sqr();
+            if norm_sqr < self.min_norm_sqr {
+                self.min_norm_sqr = norm_sqr;
+                self.atom_domain = self.iterations;
+            }
+        }
+    }
+
+    /// Updates the derivative for a step from the current z, given the derivative of the step
+    pub fn differentiate(&mut self, step_derivative: Complex64, tracking: &Tracking) {
+        self.derivative = step_derivative * self.derivative;
+        if !tracking.julia {
+            self.derivative += 1.0;
+        }
+    }
+}
+
+impl Tracking {
+    pub fn new(config: &Config, tolerance: Option<f64>) -> Self {
+        let derivative = config.colouring.mode == ColouringMode::Distance;
+        Self {
+            tolerance: if config.optimisations.periodicity_checking {
+                tolerance
+            } else {
+                None
+            },
+            derivative,
+            // r ln r / |dz| only estimates the distance well once |z| is large, otherwise lines
+            // get thicker or thinner with the escape time
+            bailout: if derivative {
+                DISTANCE_BAILOUT
+            } else {
+                config.escape_time().bailout()
+            },
+            trap: match config.orbit_trap.shape {
+                TrapShape::None => None,
+                _ => Some(config.orbit_trap),
+            },
+            atom_domain: config.interior.mode == InteriorMode::AtomDomain,
+            julia: config.fractal == Fractal::Julia,
+        }
+    }
+}
+
+fn diverge_iterations(
+    fractal: &dyn EscapeTimeFractal,
+    z: Complex64,
+    c: Complex64,
+    max: usize,
+    tracking: &Tracking,
+) -> Orbit {
+    let bailout = tracking.bailout;
+    let mut orbit = Orbit::new(z, tracking);
+    if !tracking.julia {
+        orbit.derivative = fractal.initial_derivative();
+    }
+    let mut periodicity = tracking.tolerance.map(|t| Periodicity::new(z, t));
+    while orbit.z.norm_sqr() <= bailout && orbit.iterations < max {
+        if tracking.derivative {
+            orbit.differentiate(fractal.derivative(orbit.z, c), tracking);
+        }
+        orbit.z = fractal.step(orbit.z, c);
+        orbit.iterations += 1;
+        orbit.observe(tracking);
+
+        if let Some(p) = &mut periodicity {
+            if let Some(period) = p.detect(orbit.z, orbit.z.norm_sqr(), |a, b| (a - b).norm_sqr()) {
+                // Carry on around the cycle so z ends where it would have after max iterations
+                for _ in 0..(max - orbit.iterations) % period {
+                    orbit.z = fractal.step(orbit.z, c);
+                    orbit.iterations += 1;
+                    orbit.observe(tracking);
+                }
+                orbit.iterations = max;
+                orbit.period = Some(period);
+                break;
+            }
+        }
+    }
+    orbit
+}
+
+/// Iterates z² + c in double-double precision
+pub fn diverge_iterations_double_double(
+    mut z: (DoubleDouble, DoubleDouble),
+    c: (DoubleDouble, DoubleDouble),
+    max: usize,
+    tracking: &Tracking,
+) -> Orbit {
+    let to_complex = |z: (DoubleDouble, DoubleDouble)| Complex64::new(z.0.to_f64(), z.1.to_f64());
+
+    let mut orbit = Orbit::new(to_complex(z), tracking);
+    let mut periodicity = tracking.tolerance.map(|t| Periodicity::new(z, t));
+    while z.0.hi * z.0.hi + z.1.hi * z.1.hi <= tracking.bailout && orbit.iterations < max {
+        if tracking.derivative {
+            orbit.differentiate(to_complex(z) * 2.0, tracking);
+        }
+        let reim = z.0 * z.1;
+        z = (z.0.square() - z.1.square() + c.0, reim + reim + c.1);
+        orbit.iterations += 1;
+        if tracking.trap.is_some() || tracking.atom_domain {
+            orbit.z = to_complex(z);
+            orbit.observe(tracking);
+        }
+
+        if let Some(p) = &mut periodicity {
+            let distance_sqr = |a: (DoubleDouble, DoubleDouble),
+                                b: (DoubleDouble, DoubleDouble)| {
+                let re = (a.0 - b.0).to_f64();
+                let im = (a.1 - b.1).to_f64();
+                re * re + im * im
+            };
+            let norm_sqr = z.0.hi * z.0.hi + z.1.hi * z.1.hi;
+            if let Some(period) = p.detect(z, norm_sqr, distance_sqr) {
+                for _ in 0..(max - orbit.iterations) % period {
+                    let reim = z.0 * z.1;
+                    z = (z.0.square() - z.1.square() + c.0, reim + reim + c.1);
+                    orbit.iterations += 1;
+                    if tracking.trap.is_some() || tracking.atom_domain {
+                        orbit.z = to_complex(z);
+                        orbit.observe(tracking);
+                    }
+                }
+                orbit.iterations = max;
+                orbit.period = Some(period);
+                break;
+            }
+        }
     }
-    (i, z)
+    orbit.z = to_complex(z);
+    orbit
 }
 
-fn colour_scalar(i: usize, z: Complex64, max: usize) -> f64 {
-    let log_zn = z.norm_sqr().log10() / 2f64;
-    let nu = (log_zn / 2f64.log10()).log2();
-    (i as f64 + 1f64 - nu) / max as f64
+fn colour_scalar(fractal: &dyn EscapeTimeFractal, i: usize, z: Complex64, max: usize) -> f64 {
+    fractal.smooth(i, z) / max as f64
 }
 
-pub fn colourise(c: Complex64, max: usize, gradient: &Gradient, black: Colour) -> Colour {
-    let (i, z) = diverge_iterations(c, max);
-    if i < max {
-        let scalar = colour_scalar(i, z, max);
-        gradient.get(scalar)
+/// Exterior distance estimate of an escaped orbit, in pixels
+fn distance(orbit: &Orbit, pixel_size: f64) -> f64 {
+    let r = orbit.z.norm();
+    r * r.ln() / orbit.derivative.norm() / pixel_size
+}
+
+/// Iterates a point of the plane
+pub fn iterate(
+    point: Complex64,
+    fractal: &dyn EscapeTimeFractal,
+    config: &Config,
+    tracking: &Tracking,
+) -> Orbit {
+    let (z, c) = match config.fractal {
+        Fractal::Mandelbrot => (fractal.initial(point), point),
+        Fractal::Julia => (point, Complex64::new(config.julia_c[0], config.julia_c[1])),
+    };
+
+    let mandelbrot = config.fractal == Fractal::Mandelbrot && config.formula == Formula::Mandelbrot;
+    // The other interior modes need the actual orbit of the point
+    let rejection = match config.interior.mode {
+        InteriorMode::Flat | InteriorMode::Period => config.optimisations.bulb_rejection,
+        _ => false,
+    };
+    if rejection && mandelbrot {
+        if let Some(period) = cardioid_or_bulb_period(c) {
+            let mut orbit = Orbit::new(z, tracking);
+            orbit.iterations = config.max_iterations;
+            orbit.period = Some(period);
+            return orbit;
+        }
+    }
+
+    let mut orbit = diverge_iterations(fractal, z, c, config.max_iterations, tracking);
+    if mandelbrot && config.interior.mode == InteriorMode::Distance {
+        if let Some(period) = orbit.period {
+            orbit.interior_distance = interior_distance(orbit.z, c, period);
+        }
+    }
+    orbit
+}
+
+/// Interior distance estimate of a point of the Mandelbrot set, given a point close to its
+/// attracting cycle and the period of that cycle
+///
+/// The point is first refined onto the cycle with Newton's method, then the derivatives along
+/// the cycle give the estimate.
+fn interior_distance(mut z0: Complex64, c: Complex64, period: usize) -> Option<f64> {
+    for _ in 0..16 {
+        let (mut z, mut dz) = (z0, Complex64::one());
+        for _ in 0..period {
+            dz = z * dz * 2.0;
+            z = z * z + c;
+        }
+        let step = (z - z0) / (dz - 1.0);
+        z0 -= step;
+        if step.norm_sqr() < 1e-24 {
+            break;
+        }
+    }
+
+    let mut z = z0;
+    let (mut dz, mut dc) = (Complex64::one(), Complex64::zero());
+    let (mut dzdz, mut dcdz) = (Complex64::zero(), Complex64::zero());
+    for _ in 0..period {
+        dcdz = (z * dcdz + dz * dc) * 2.0;
+        dc = z * dc * 2.0 + 1.0;
+        dzdz = (dz * dz + z * dzdz) * 2.0;
+        dz = z * dz * 2.0;
+        z = z * z + c;
+    }
+
+    let multiplier = dz.norm_sqr();
+    if multiplier > 1.0 {
+        return None;
+    }
+    let distance = (1.0 - multiplier) / (dcdz + dzdz * dc / (Complex64::one() - dz)).norm();
+    if distance.is_finite() {
+        Some(distance)
     } else {
-        black
+        None
+    }
+}
+
+/// Colours an orbit, given the size of a pixel
+pub fn colourise(
+    fractal: &dyn EscapeTimeFractal,
+    orbit: &Orbit,
+    config: &Config,
+    pixel_size: f64,
+) -> Colour {
+    let max = config.max_iterations;
+    if orbit.iterations >= max {
+        return interior(fractal, orbit, config, pixel_size);
+    }
+
+    let scalar = colour_scalar(fractal, orbit.iterations, orbit.z, max);
+    let mut colour = config.gradient.get(scalar);
+    if orbit.trap.is_finite() {
+        let trap = config
+            .gradient
+            .get((orbit.trap / config.orbit_trap.scale).min(1.0));
+        colour = colour.mix(trap, config.orbit_trap.blend);
+    }
+    match config.colouring.mode {
+        ColouringMode::Smooth => colour,
+        ColouringMode::Distance => {
+            let factor = (distance(orbit, pixel_size) / config.colouring.thickness).min(1.0);
+            config.colouring.line.mix(colour, factor)
+        }
+    }
+}
+
+/// Colours a point of the set
+fn interior(
+    fractal: &dyn EscapeTimeFractal,
+    orbit: &Orbit,
+    config: &Config,
+    pixel_size: f64,
+) -> Colour {
+    let interior = &config.interior;
+    // Periods and iterations are spread on a log scale so the small ones stay distinct
+    let log_scale = |n: usize| (n as f64).ln() / (config.max_iterations.max(2) as f64).ln();
+    let (scalar, gradient) = match interior.mode {
+        InteriorMode::Flat => return config.black,
+        InteriorMode::Magnitude => (
+            orbit.z.norm() / fractal.bailout().sqrt(),
+            &interior.magnitude,
+        ),
+        InteriorMode::Period => match orbit.period {
+            Some(period) => (log_scale(period), &interior.period),
+            None => return config.black,
+        },
+        // Distance to the boundary in pixels, reaching the end of the gradient at about 20000
+        InteriorMode::Distance => match orbit.interior_distance {
+            Some(distance) => (
+                (1.0 + distance / pixel_size).ln() / 10.0,
+                &interior.distance,
+            ),
+            None => return config.black,
+        },
+        InteriorMode::AtomDomain => (log_scale(orbit.atom_domain), &interior.atom_domain),
+    };
+    gradient
+        .as_ref()
+        .unwrap_or(&config.gradient)
+        .get(scalar.clamp(0.0, 1.0))
+}
+
+/// Height of an escaped orbit used for lighting, from the same field as the colouring
+pub fn height(
+    fractal: &dyn EscapeTimeFractal,
+    orbit: &Orbit,
+    config: &Config,
+    pixel_size: f64,
+) -> Option<f64> {
+    if orbit.iterations >= config.max_iterations {
+        return None;
+    }
+
+    match config.colouring.mode {
+        ColouringMode::Smooth => Some(fractal.smooth(orbit.iterations, orbit.z)),
+        ColouringMode::Distance => Some(distance(orbit, pixel_size).ln()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derivatives_match_finite_differences() {
+        let mut config = Config::default();
+        config.colouring.mode = ColouringMode::Distance;
+        config.max_iterations = 6;
+        // The point is in the main cardioid, which would be skipped
+        config.optimisations.bulb_rejection = false;
+        let tracking = Tracking::new(&config, None);
+        for (formula, exponent) in [(Formula::Mandelbrot, 2.0), (Formula::Multibrot, -2.0)] {
+            config.formula = formula.clone();
+            config.multibrot_exponent = exponent;
+            let fractal = config.escape_time();
+            let c = Complex64::new(0.3, 0.4);
+            let h = 1e-7;
+            let orbit = iterate(c, fractal.as_ref(), &config, &tracking);
+            let shifted = iterate(c + h, fractal.as_ref(), &config, &tracking);
+            assert_eq!(orbit.iterations, config.max_iterations);
+
+            let difference = (shifted.z - orbit.z) / h;
+            let error = (difference - orbit.derivative).norm() / orbit.derivative.norm();
+            assert!(
+                error < 1e-4,
+                "{:?}: {} against {}",
+                formula,
+                orbit.derivative,
+                difference
+            );
+        }
     }
 }
@@ -0,0 +1,111 @@
+use crate::{config::Config, view::View};
+use anyhow::{Error, Result};
+use png::Decoder;
+use std::{fs::File, path::Path};
+use toml::{value::Table, Value};
+
+/// Keyword of the chunk holding the whole configuration, the other ones are only informative
+const CONFIGURATION: &str = "Configuration";
+
+/// Text chunks describing a render, given its view already scaled to the render resolution
+pub fn text_chunks(view: &View, config: &Config) -> Result<Vec<(String, String)>> {
+    let digits = view.digits();
+    let formula = match config.source.get("formula") {
+        Some(Value::String(formula)) => formula.clone(),
+        _ => "mandelbrot".to_owned(),
+    };
+    Ok(vec![
+        (
+            "Software".to_owned(),
+            format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        ),
+        (
+            "Center".to_owned(),
+            format!("{:.*}, {:.*}", digits, view.center.0, digits, view.center.1),
+        ),
+        (
+            "Size".to_owned(),
+            format!("{:e}", view.dimensions(config.render.resolution).0),
+        ),
+        (
+            "Max iterations".to_owned(),
+            config.max_iterations.to_string(),
+        ),
+        ("Formula".to_owned(), formula),
+        ("Gradient".to_owned(), toml::to_string(&config.gradient)?),
+        (CONFIGURATION.to_owned(), configuration(view, config)?),
+    ])
+}
+
+/// Configuration rendering the same image, given its view already scaled to the render resolution
+pub fn configuration(view: &View, config: &Config) -> Result<String> {
+    // The configuration describes the preview the render was scaled from
+    let mut preview = view.clone();
+    preview.scale_factor = crate::scale(
+        view.scale_factor,
+        crate::render_size(config),
+        crate::preview_size(config),
+    );
+    // Every bit of the centre is a finite decimal, so it reads back exactly
+    let center = [
+        view.center.0.to_decimal(view.center.0.bits()),
+        view.center.1.to_decimal(view.center.1.bits()),
+    ];
+
+    let mut source = config.source.clone();
+    source.insert(
+        "max-iterations".to_owned(),
+        Value::Integer(config.max_iterations as _),
+    );
+    source.insert("julia-c".to_owned(), Value::try_from(config.julia_c)?);
+    source.insert("gradient".to_owned(), Value::try_from(&config.gradient)?);
+    let view_table = table(&mut source, "view");
+    view_table.insert("center".to_owned(), Value::try_from(&center)?);
+    view_table.insert("zoom".to_owned(), Value::Float(preview.zoom_level(config)));
+    view_table.insert("rotation".to_owned(), Value::Float(view.rotation));
+    let render_table = table(&mut source, "render");
+    render_table.insert(
+        "width".to_owned(),
+        Value::Integer(config.render.resolution.width as _),
+    );
+    render_table.insert(
+        "height".to_owned(),
+        Value::Integer(config.render.resolution.height as _),
+    );
+
+    // Tables only put their plain values first when wrapped in a value
+    Ok(toml::to_string(&Value::Table(source))?)
+}
+
+/// Reads the configuration stored in a rendered image, which starts on the view of the image
+pub fn read(path: &Path) -> Result<Config> {
+    let reader = Decoder::new(File::open(path)?).read_info()?;
+    let info = reader.info();
+    let latin1 = info
+        .uncompressed_latin1_text
+        .iter()
+        .filter(|chunk| chunk.keyword == CONFIGURATION)
+        .map(|chunk| Ok(chunk.text.clone()));
+    let utf8 = info
+        .utf8_text
+        .iter()
+        .filter(|chunk| chunk.keyword == CONFIGURATION)
+        .map(|chunk| Ok(chunk.get_text()?));
+    let source = latin1
+        .chain(utf8)
+        .next()
+        .unwrap_or_else(|| Err(Error::msg("the image doesn't hold a configuration")))?;
+
+    crate::config::from_table(toml::from_str(&source)?)
+}
+
+/// Table under the given key, replacing anything else that was there
+fn table<'a>(parent: &'a mut Table, key: &str) -> &'a mut Table {
+    if !matches!(parent.get(key), Some(Value::Table(_))) {
+        parent.insert(key.to_owned(), Value::Table(Table::new()));
+    }
+    match parent.get_mut(key) {
+        Some(Value::Table(table)) => table,
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{
+    config::{Config, Fractal},
+    fixed::Fixed,
+    mandelbrot::{Orbit, Tracking},
+};
+use num_complex::Complex64;
+use num_traits::Zero;
+
+/// Orbit of the view centre computed at arbitrary precision, used as the reference from
+/// which every pixel is iterated as a small f64 delta
+pub struct ReferenceOrbit {
+    orbit: Vec<Complex64>,
+    julia: bool,
+}
+
+impl ReferenceOrbit {
+    /// Iterates the centre until it leaves the squared bailout radius of the pixels
+    pub fn new(center: &(Fixed, Fixed), bits: usize, config: &Config, bailout: f64) -> Self {
+        let (re, im) = (center.0.with_bits(bits), center.1.with_bits(bits));
+        let (mut z, c) = match config.fractal {
+            Fractal::Mandelbrot => ((Fixed::zero(bits), Fixed::zero(bits)), (re, im)),
+            Fractal::Julia => (
+                (re, im),
+                (
+                    Fixed::from_f64(config.julia_c[0], bits),
+                    Fixed::from_f64(config.julia_c[1], bits),
+                ),
+            ),
+        };
+
+        let max = config.max_iterations;
+        let mut orbit = Vec::with_capacity(max + 1);
+        loop {
+            let point = Complex64::new(z.0.to_f64(), z.1.to_f64());
+            orbit.push(point);
+            // Always keep at least one step so pixels can be iterated from the start
+            if orbit.len() > max || (orbit.len() > 1 && point.norm_sqr() > bailout) {
+                break;
+            }
+
+            let re2 = &z.0 * &z.0;
+            let im2 = &z.1 * &z.1;
+            let reim = &z.0 * &z.1;
+            z = (&(&re2 - &im2) + &c.0, &(&reim + &reim) + &c.1);
+        }
+
+        Self {
+            orbit,
+            julia: config.fractal == Fractal::Julia,
+        }
+    }
+
+    /// Iterates the pixel at the given offset from the reference
+    ///
+    /// Whenever the pixel orbit gets closer to the start of the reference orbit than to the
+    /// reference itself the delta loses precision and the pixel would glitch, so it is rebased
+    /// onto the start of the reference. The same happens when the reference escapes first.
+    pub fn diverge_iterations(&self, delta: Complex64, max: usize, tracking: &Tracking) -> Orbit {
+        let (mut dz, dc) = if self.julia {
+            (delta, Complex64::zero())
+        } else {
+            (Complex64::zero(), delta)
+        };
+
+        let start = self.orbit[0];
+        let mut m = 0;
+        let mut orbit = Orbit::new(start + dz, tracking);
+        while orbit.z.norm_sqr() <= tracking.bailout && orbit.iterations < max {
+            if tracking.derivative {
+                orbit.differentiate(orbit.z * 2.0, tracking);
+            }
+            dz = (self.orbit[m] * 2.0 + dz) * dz + dc;
+            m += 1;
+            orbit.z = self.orbit[m] + dz;
+            orbit.iterations += 1;
+            orbit.observe(tracking);
+
+            if m == self.orbit.len() - 1 || (orbit.z - start).norm_sqr() < dz.norm_sqr() {
+                dz = orbit.z - start;
+                m = 0;
+            }
+        }
+        orbit
+    }
+}
@@ -0,0 +1,327 @@
+use crate::{
+    colour::Colour,
+    config::Config,
+    renderer::{Renderer, Sample},
+    view::View,
+};
+use ndarray::{s, Array2, Zip};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SendError, Sender, TryRecvError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Side of the blocks of the first pass, each following pass halves it
+const COARSEST_BLOCK: usize = 8;
+
+/// Renders the preview on a background thread, in passes going from coarse blocks to pixels
+///
+/// Requesting a view cancels the one in progress, so the event loop never waits on a frame.
+pub struct PreviewWorker {
+    requests: Sender<Request>,
+    frames: Receiver<Frame>,
+    /// Number of the last requested view, passes of any other one are dropped
+    generation: Arc<AtomicUsize>,
+    busy: bool,
+}
+
+struct Request {
+    generation: usize,
+    /// Whether the configuration changed, so no pixels can be kept
+    reload: bool,
+    requested: Instant,
+    view: View,
+    config: Config,
+}
+
+/// A finished pass of the preview, as rows of RGB pixels
+pub struct Frame {
+    pub pixels: Vec<u8>,
+    pub width: usize,
+    /// Side of the blocks of the pass, 1 once the preview is complete
+    pub block: usize,
+    /// Time since the view was requested
+    pub elapsed: Duration,
+    generation: usize,
+}
+
+impl PreviewWorker {
+    pub fn new() -> Self {
+        let generation = Arc::new(AtomicUsize::new(0));
+        let (requests, frames) = spawn(generation.clone());
+        Self {
+            requests,
+            frames,
+            generation,
+            busy: false,
+        }
+    }
+
+    /// Starts rendering a view, dropping the one in progress
+    pub fn request(&mut self, view: &View, config: &Config) {
+        self.send(view, config, false);
+    }
+
+    /// Starts rendering a view with a new configuration, which can't reuse any pixel
+    pub fn reload(&mut self, view: &View, config: &Config) {
+        self.send(view, config, true);
+    }
+
+    fn send(&mut self, view: &View, config: &Config, reload: bool) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.busy = true;
+        let request = Request {
+            generation,
+            reload,
+            requested: Instant::now(),
+            view: view.clone(),
+            config: config.clone(),
+        };
+        if let Err(SendError(request)) = self.requests.send(request) {
+            self.restart();
+            // The new worker is waiting for its first request
+            let _ = self.requests.send(request);
+        }
+    }
+
+    /// Replaces a worker which panicked, the view it was rendering isn't requested again in case
+    /// it panics every time
+    fn restart(&mut self) {
+        eprintln!(
+            "[{}] [PREVIEW] [ERROR] The worker panicked, restarting it",
+            now!()
+        );
+        let (requests, frames) = spawn(self.generation.clone());
+        self.requests = requests;
+        self.frames = frames;
+        self.busy = false;
+    }
+
+    /// Whether the last requested view has passes left
+    pub fn busy(&self) -> bool {
+        self.busy
+    }
+
+    /// Latest pass of the last requested view, if one finished since the last call
+    pub fn poll(&mut self) -> Option<Frame> {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let mut latest = None;
+        loop {
+            match self.frames.try_recv() {
+                Ok(frame) if frame.generation == generation => {
+                    self.busy &= frame.block > 1;
+                    latest = Some(frame);
+                }
+                Ok(_) => (),
+                Err(TryRecvError::Empty) => break,
+                // The worker only lets go of the frames once it stops, which it doesn't on its own
+                Err(TryRecvError::Disconnected) => {
+                    self.restart();
+                    break;
+                }
+            }
+        }
+        latest
+    }
+}
+
+impl Drop for PreviewWorker {
+    fn drop(&mut self) {
+        // Cancels the view in progress, the worker stops once the requests are disconnected
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Samples of the last complete preview, which panning can partly reuse
+struct Kept {
+    view: View,
+    samples: Array2<Sample>,
+}
+
+/// Starts a worker, returning where to send it requests and where it sends frames
+fn spawn(generation: Arc<AtomicUsize>) -> (Sender<Request>, Receiver<Frame>) {
+    let (requests, requests_rx) = mpsc::channel();
+    let (frames_tx, frames) = mpsc::channel();
+    thread::spawn(move || work(requests_rx, frames_tx, generation));
+    (requests, frames)
+}
+
+fn work(requests: Receiver<Request>, frames: Sender<Frame>, generation: Arc<AtomicUsize>) {
+    let mut kept = None;
+    while let Ok(mut request) = requests.recv() {
+        // Views requested while the last one was rendering are already outdated but the newest
+        let mut reload = request.reload;
+        while let Ok(newer) = requests.try_recv() {
+            reload |= newer.reload;
+            request = newer;
+        }
+        if reload {
+            kept = None;
+        }
+
+        let cancelled = || generation.load(Ordering::SeqCst) != request.generation;
+        if !render(&request, &cancelled, &frames, &mut kept) {
+            break;
+        }
+    }
+}
+
+/// Renders the passes of a view until it's cancelled, returns false once nobody is listening
+///
+/// When the view was only moved by whole pixels since the kept preview, the samples still in
+/// sight are shifted and only the uncovered strips are rendered, in a single pass.
+fn render(
+    request: &Request,
+    cancelled: &(dyn Fn() -> bool + Sync),
+    frames: &Sender<Frame>,
+    kept: &mut Option<Kept>,
+) -> bool {
+    let config = &request.config;
+    let resolution = config.preview.resolution;
+    let renderer = Renderer::new(&request.view, resolution, config);
+    let blank = Sample {
+        colour: Colour { r: 0, g: 0, b: 0 },
+        height: None,
+    };
+
+    let shift = kept
+        .as_ref()
+        .and_then(|previous| Some((previous, previous.view.pixel_shift(&request.view)?)))
+        .filter(|(previous, (dx, dy))| {
+            previous.samples.dim() == (resolution.width, resolution.height)
+                && dx.unsigned_abs() < resolution.width
+                && dy.unsigned_abs() < resolution.height
+        });
+    if let Some((previous, (dx, dy))) = shift {
+        let (width, height) = (resolution.width as isize, resolution.height as isize);
+        // Pixels of the kept preview, at the place they moved to
+        let old = |x: usize, y: usize| {
+            let (x, y) = (x as isize + dx, y as isize + dy);
+            if (0..width).contains(&x) && (0..height).contains(&y) {
+                Some(previous.samples[[x as usize, y as usize]])
+            } else {
+                None
+            }
+        };
+        let mut samples = Array2::from_elem((resolution.width, resolution.height), blank);
+        Zip::indexed(&mut samples).par_apply(|(x, y), sample| {
+            if let Some(old) = old(x, y) {
+                *sample = old;
+            } else if !cancelled() {
+                *sample = renderer.sample(x, y);
+            }
+        });
+        if cancelled() {
+            return true;
+        }
+        return finish(request, samples, frames, kept);
+    }
+
+    let mut samples = Array2::from_elem((resolution.width, resolution.height), blank);
+    let mut block = COARSEST_BLOCK;
+    while block > 1 {
+        if !pass(&renderer, &mut samples, block, cancelled) {
+            return true;
+        }
+        let frame = frame(request, &samples, block);
+        if frames.send(frame).is_err() {
+            return false;
+        }
+        block /= 2;
+    }
+    if !pass(&renderer, &mut samples, 1, cancelled) {
+        return true;
+    }
+    finish(request, samples, frames, kept)
+}
+
+/// Samples the points of a pass which the previous ones didn't, returns false if cancelled
+fn pass(
+    renderer: &Renderer,
+    samples: &mut Array2<Sample>,
+    block: usize,
+    cancelled: &(dyn Fn() -> bool + Sync),
+) -> bool {
+    let step = block as isize;
+    Zip::indexed(samples.slice_mut(s![..;step, ..;step])).par_apply(|(i, j), sample| {
+        // Points on both even rows and columns were sampled by the previous pass
+        let sampled = block < COARSEST_BLOCK && i % 2 == 0 && j % 2 == 0;
+        if !sampled && !cancelled() {
+            *sample = renderer.sample(i * block, j * block);
+        }
+    });
+    !cancelled()
+}
+
+/// Sends the complete preview and keeps its samples for the next one
+fn finish(
+    request: &Request,
+    samples: Array2<Sample>,
+    frames: &Sender<Frame>,
+    kept: &mut Option<Kept>,
+) -> bool {
+    let frame = frame(request, &samples, 1);
+    *kept = Some(Kept {
+        view: request.view.clone(),
+        samples,
+    });
+    frames.send(frame).is_ok()
+}
+
+/// Lights the points sampled by a pass and spreads them over their blocks
+fn frame(request: &Request, samples: &Array2<Sample>, block: usize) -> Frame {
+    let (width, height) = samples.dim();
+    let step = block as isize;
+    let coarse = samples.slice(s![..;step, ..;step]).to_owned();
+    // Slopes between blocks are steeper than between pixels
+    let colours = crate::lighting::shade(&coarse, &request.config.lighting, 1.0 / block as f64);
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let colour = colours[[x / block, y / block]];
+            pixels.extend_from_slice(&[colour.r, colour.g, colour.b]);
+        }
+    }
+    Frame {
+        pixels,
+        width,
+        block,
+        elapsed: request.requested.elapsed(),
+        generation: request.generation,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Resolution;
+
+    #[test]
+    fn requests_reach_a_restarted_worker() {
+        let mut config = Config::default();
+        config.preview.resolution = Resolution {
+            width: 16,
+            height: 16,
+        };
+        let view = View::initial(&config);
+        let mut worker = PreviewWorker::new();
+        // Same as a worker which panicked
+        let (requests, _) = mpsc::channel();
+        worker.requests = requests;
+
+        worker.request(&view, &config);
+        for _ in 0..1000 {
+            if let Some(frame) = worker.poll() {
+                if frame.block == 1 {
+                    return;
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the preview never finished");
+    }
+}
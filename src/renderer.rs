@@ -0,0 +1,370 @@
+use crate::{
+    colour::Colour,
+    config::{Config, Formula, Fractal, InteriorMode, Resolution},
+    double_double::DoubleDouble,
+    formula::EscapeTimeFractal,
+    mandelbrot::{Orbit, Tracking},
+    perturbation::ReferenceOrbit,
+    view::View,
+};
+use num_complex::Complex64;
+use std::{fmt, sync::Mutex};
+
+/// Below this pixel size f64 coordinates can no longer tell neighbouring pixels apart
+const F64_LIMIT: f64 = 1e-13;
+/// Same as above for double-double coordinates
+const DOUBLE_DOUBLE_LIMIT: f64 = 1e-28;
+
+/// Interior mode and precision last warned about, so every preview doesn't repeat it
+static WARNED: Mutex<Option<(InteriorMode, Precision)>> = Mutex::new(None);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Precision {
+    Double,
+    DoubleDouble,
+    Perturbation,
+}
+
+/// How the pixels are laid out on the plane
+#[derive(Debug, Copy, Clone)]
+pub enum Mapping {
+    Cartesian,
+    /// Log-polar coordinates around the centre, x is the angle and y the log of the radius,
+    /// going inwards from the top by step per pixel in both directions
+    Exponential {
+        log_radius: f64,
+        step: f64,
+    },
+}
+
+/// Colour of a pixel, and its height for lighting if it's outside of the set
+#[derive(Debug, Copy, Clone)]
+pub struct Sample {
+    pub colour: Colour,
+    pub height: Option<f64>,
+}
+
+enum Sampler {
+    Double {
+        center: Complex64,
+    },
+    DoubleDouble {
+        center: (DoubleDouble, DoubleDouble),
+    },
+    Perturbation {
+        reference: ReferenceOrbit,
+    },
+}
+
+/// Colours the pixels of a view, using whichever precision its scale requires
+pub struct Renderer<'a> {
+    config: &'a Config,
+    fractal: Box<dyn EscapeTimeFractal>,
+    resolution: Resolution,
+    scale_factor: f64,
+    /// Sine and cosine of the rotation of the view
+    rotation: (f64, f64),
+    mapping: Mapping,
+    tracking: Tracking,
+    sampler: Sampler,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(view: &View, resolution: Resolution, config: &'a Config) -> Self {
+        let precision = Precision::for_view(view, config);
+        warn_interior(config.interior.mode, precision);
+        let tolerance = match precision {
+            // f64 orbits can't tell a cycle apart from a pixel slowly drifting away from it at
+            // perturbation depths
+            Precision::Perturbation => None,
+            // Slowly escaping orbits can creep by less than a pixel per iteration, so only points
+            // equal to the resolution of the coordinates are taken for a cycle
+            Precision::Double => Some(1e-12),
+            Precision::DoubleDouble => Some(1e-30),
+        };
+        let tracking = Tracking::new(config, tolerance);
+        let sampler = match precision {
+            Precision::Double => Sampler::Double {
+                center: Complex64::new(view.center.0.to_f64(), view.center.1.to_f64()),
+            },
+            Precision::DoubleDouble => Sampler::DoubleDouble {
+                center: (
+                    DoubleDouble::from_fixed(&view.center.0),
+                    DoubleDouble::from_fixed(&view.center.1),
+                ),
+            },
+            Precision::Perturbation => Sampler::Perturbation {
+                reference: ReferenceOrbit::new(&view.center, view.bits(), config, tracking.bailout),
+            },
+        };
+
+        Self {
+            config,
+            fractal: config.escape_time(),
+            resolution,
+            scale_factor: view.scale_factor,
+            rotation: (-view.rotation.to_radians()).sin_cos(),
+            mapping: Mapping::Cartesian,
+            tracking,
+            sampler,
+        }
+    }
+
+    /// Uses another layout for the pixels, the precision is still picked from the scale of the
+    /// view so it should be the size of the smallest pixels
+    pub fn with_mapping(mut self, mapping: Mapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    pub fn precision(&self) -> Precision {
+        match self.sampler {
+            Sampler::Double { .. } => Precision::Double,
+            Sampler::DoubleDouble { .. } => Precision::DoubleDouble,
+            Sampler::Perturbation { .. } => Precision::Perturbation,
+        }
+    }
+
+    pub fn sample(&self, x: usize, y: usize) -> Sample {
+        let orbit = self.orbit(x, y);
+        let fractal = self.fractal.as_ref();
+        let pixel_size = self.pixel_size(y);
+        Sample {
+            colour: crate::mandelbrot::colourise(fractal, &orbit, self.config, pixel_size),
+            height: crate::mandelbrot::height(fractal, &orbit, self.config, pixel_size),
+        }
+    }
+
+    fn orbit(&self, x: usize, y: usize) -> Orbit {
+        let max = self.config.max_iterations;
+        let tracking = &self.tracking;
+        match &self.sampler {
+            Sampler::Double { center } => {
+                let c = center + self.delta(x, y);
+                crate::mandelbrot::iterate(c, self.fractal.as_ref(), self.config, tracking)
+            }
+            Sampler::DoubleDouble { center } => {
+                let delta = self.delta(x, y);
+                let point = (
+                    center.0 + DoubleDouble::from(delta.re),
+                    center.1 + DoubleDouble::from(delta.im),
+                );
+                let zero = DoubleDouble::from(0.0);
+                let (z, c) = match self.config.fractal {
+                    Fractal::Mandelbrot => ((zero, zero), point),
+                    Fractal::Julia => (
+                        point,
+                        (
+                            DoubleDouble::from(self.config.julia_c[0]),
+                            DoubleDouble::from(self.config.julia_c[1]),
+                        ),
+                    ),
+                };
+                crate::mandelbrot::diverge_iterations_double_double(z, c, max, tracking)
+            }
+            Sampler::Perturbation { reference } => {
+                reference.diverge_iterations(self.delta(x, y), max, tracking)
+            }
+        }
+    }
+
+    /// Offset of a pixel from the centre of the view
+    fn delta(&self, x: usize, y: usize) -> Complex64 {
+        let (dx, dy) = match self.mapping {
+            Mapping::Cartesian => (
+                (x as f64 - self.resolution.width as f64 / 2.0) * self.scale_factor,
+                (y as f64 - self.resolution.height as f64 / 2.0) * self.scale_factor,
+            ),
+            Mapping::Exponential { log_radius, step } => {
+                let radius = (log_radius - y as f64 * step).exp();
+                let (sin, cos) = (x as f64 * step).sin_cos();
+                (radius * cos, radius * sin)
+            }
+        };
+        let (sin, cos) = self.rotation;
+        Complex64::new(dx * cos - dy * sin, dx * sin + dy * cos)
+    }
+
+    /// Size of the pixels of a row in the plane
+    fn pixel_size(&self, y: usize) -> f64 {
+        match self.mapping {
+            Mapping::Cartesian => self.scale_factor,
+            Mapping::Exponential { log_radius, step } => {
+                (log_radius - y as f64 * step).exp() * step
+            }
+        }
+    }
+}
+
+/// Warns when the points of the set are drawn black because the precision tier doesn't track
+/// what the interior mode needs
+fn warn_interior(mode: InteriorMode, precision: Precision) {
+    let missing = match (mode, precision) {
+        // Periodicity checking is disabled for perturbation
+        (InteriorMode::Period, Precision::Perturbation) => Some("period"),
+        // Refining the cycle needs the orbit in f64
+        (InteriorMode::Distance, Precision::DoubleDouble | Precision::Perturbation) => {
+            Some("distance")
+        }
+        _ => None,
+    };
+    let mut warned = WARNED.lock().unwrap();
+    match missing {
+        Some(name) if *warned != Some((mode, precision)) => {
+            eprintln!(
+                "[{}] [RENDER] [WARNING] The {} interior mode isn't available in {} precision, \
+                 the set uses the black colour",
+                now!(),
+                name,
+                precision
+            );
+            *warned = Some((mode, precision));
+        }
+        Some(_) => (),
+        None => *warned = None,
+    }
+}
+
+impl Precision {
+    /// Picks the cheapest precision tier able to render the view
+    pub fn for_view(view: &View, config: &Config) -> Self {
+        // Higher precision tiers are only implemented for z² + c
+        if !config.deep_zoom.enabled
+            || config.formula != Formula::Mandelbrot
+            || view.scale_factor >= F64_LIMIT
+        {
+            Precision::Double
+        } else if view.scale_factor >= DOUBLE_DOUBLE_LIMIT {
+            Precision::DoubleDouble
+        } else {
+            Precision::Perturbation
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Precision::Double => f.write_str("f64"),
+            Precision::DoubleDouble => f.write_str("double-double"),
+            Precision::Perturbation => f.write_str("perturbation"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixed::Fixed;
+
+    /// Escape times of every pixel of a view, with periodicity checking on or off
+    fn iterations(
+        config: &Config,
+        center: (&str, &str),
+        scale_factor: f64,
+        checking: bool,
+    ) -> Vec<usize> {
+        let mut config = config.clone();
+        config.max_iterations = 4096;
+        config.optimisations.periodicity_checking = checking;
+        config.optimisations.bulb_rejection = false;
+        let view = View::new(
+            (
+                Fixed::parse(center.0).unwrap(),
+                Fixed::parse(center.1).unwrap(),
+            ),
+            scale_factor,
+            0.0,
+        );
+        let resolution = Resolution {
+            width: 16,
+            height: 16,
+        };
+        let renderer = Renderer::new(&view, resolution, &config);
+        (0..resolution.height)
+            .flat_map(|y| (0..resolution.width).map(move |x| (x, y)))
+            .map(|(x, y)| renderer.orbit(x, y).iterations)
+            .collect()
+    }
+
+    #[test]
+    fn periodicity_checking_keeps_escape_times() {
+        let mandelbrot = Config::default();
+        // Orbits escaping the Julia set of the cusp creep past its parabolic point by much less
+        // than a pixel per iteration
+        let julia = Config {
+            fractal: Fractal::Julia,
+            julia_c: [0.25, 0.0],
+            ..Config::default()
+        };
+        let views = [
+            (&mandelbrot, ("0.25", "0"), 1e-6),
+            (&mandelbrot, ("-0.75", "0"), 1e-5),
+            (&julia, ("0.5", "0"), 1e-4),
+        ];
+        for (config, center, scale_factor) in views {
+            assert_eq!(
+                iterations(config, center, scale_factor, true),
+                iterations(config, center, scale_factor, false),
+                "escape times around {:?} changed",
+                center
+            );
+        }
+    }
+
+    #[test]
+    fn deep_views_render_in_double_double() {
+        let config = Config::default();
+        // Left of -2 the distance to the set grows fourfold every iteration until it escapes,
+        // while f64 coordinates would round the whole view onto -2 itself
+        let view = View::new(
+            (Fixed::parse("-2").unwrap(), Fixed::parse("0").unwrap()),
+            1e-20,
+            0.0,
+        );
+        assert_eq!(Precision::for_view(&view, &config), Precision::DoubleDouble);
+
+        let resolution = Resolution {
+            width: 8,
+            height: 8,
+        };
+        let renderer = Renderer::new(&view, resolution, &config);
+        assert_eq!(renderer.precision(), Precision::DoubleDouble);
+        let mut colours: Vec<_> = (0..resolution.height)
+            .flat_map(|y| (0..resolution.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let colour = renderer.sample(x, y).colour;
+                (colour.r, colour.g, colour.b)
+            })
+            .collect();
+        colours.dedup();
+        assert!(colours.len() > 1, "every pixel is {:?}", colours[0]);
+    }
+
+    #[test]
+    fn atom_domains_are_tracked_at_every_precision() {
+        let mut config = Config::default();
+        config.interior.mode = InteriorMode::AtomDomain;
+        let resolution = Resolution {
+            width: 4,
+            height: 4,
+        };
+        for (scale_factor, precision) in [
+            (1e-3, Precision::Double),
+            (1e-20, Precision::DoubleDouble),
+            (1e-30, Precision::Perturbation),
+        ] {
+            // Inside the main cardioid, so every pixel is a point of the set
+            let view = View::new(
+                (Fixed::parse("-0.1").unwrap(), Fixed::parse("0").unwrap()),
+                scale_factor,
+                0.0,
+            );
+            let renderer = Renderer::new(&view, resolution, &config);
+            assert_eq!(renderer.precision(), precision);
+            let orbit = renderer.orbit(1, 2);
+            assert_eq!(orbit.iterations, config.max_iterations);
+            assert!(orbit.atom_domain > 0, "no atom domain in {}", precision);
+        }
+    }
+}
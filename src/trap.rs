@@ -0,0 +1,26 @@
+use crate::config::{OrbitTrapConfig, TrapShape};
+use num_complex::Complex64;
+
+/// Distance from z to the trap, or infinity if z isn't caught by it
+pub fn distance(trap: &OrbitTrapConfig, z: Complex64) -> f64 {
+    let z = z - Complex64::new(trap.center[0], trap.center[1]);
+    // Rotate z so the trap lines are along the axes
+    let (sin, cos) = (-trap.angle.to_radians()).sin_cos();
+    let (x, y) = (z.re * cos - z.im * sin, z.re * sin + z.im * cos);
+
+    match trap.shape {
+        TrapShape::None => f64::INFINITY,
+        TrapShape::Point => z.norm(),
+        TrapShape::Line => y.abs(),
+        TrapShape::Cross => x.abs().min(y.abs()),
+        TrapShape::Circle => (z.norm() - trap.radius).abs(),
+        TrapShape::Stalk => {
+            let distance = x.abs().min(y.abs());
+            if distance < trap.width {
+                distance
+            } else {
+                f64::INFINITY
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{
+    config::{Config, Fractal, Resolution, ViewConfig},
+    fixed::{Fixed, MIN_BITS},
+    mandelbrot::Area,
+};
+
+/// Visible part of the plane, centred on an arbitrary precision point
+#[derive(Debug, Clone)]
+pub struct View {
+    pub center: (Fixed, Fixed),
+    /// Size of a pixel in the plane
+    pub scale_factor: f64,
+    /// Counterclockwise rotation of the view in degrees
+    pub rotation: f64,
+}
+
+impl View {
+    pub fn initial(config: &Config) -> Self {
+        Self::from_config(config, &config.view)
+    }
+
+    /// View described like the configured one, for the configured fractal
+    pub fn from_config(config: &Config, view: &ViewConfig) -> Self {
+        let area = area(config);
+        let scale_factor = unzoomed_scale_factor(config) / view.zoom;
+        let center = match &view.center {
+            Some([re, im]) => (re.clone(), im.clone()),
+            None => (
+                Fixed::from_f64((area.x_start + area.x_end) / 2.0, MIN_BITS),
+                Fixed::from_f64((area.y_start + area.y_end) / 2.0, MIN_BITS),
+            ),
+        };
+
+        Self::new(center, scale_factor, view.rotation)
+    }
+
+    pub fn new(center: (Fixed, Fixed), scale_factor: f64, rotation: f64) -> Self {
+        let mut view = Self {
+            center,
+            scale_factor,
+            rotation,
+        };
+        view.refine();
+        view
+    }
+
+    /// Bits of precision needed to tell pixels apart at this scale
+    pub fn bits(&self) -> usize {
+        MIN_BITS + (-self.scale_factor.log2()).max(0.0).ceil() as usize
+    }
+
+    /// Number of decimal digits needed to tell pixels apart at this scale
+    pub fn digits(&self) -> usize {
+        (-self.scale_factor.log10()).max(0.0).ceil() as usize + 3
+    }
+
+    /// Zoom relative to the initial view of the configured fractal
+    pub fn zoom_level(&self, config: &Config) -> f64 {
+        unzoomed_scale_factor(config) / self.scale_factor
+    }
+
+    pub fn dimensions(&self, resolution: Resolution) -> (f64, f64) {
+        (
+            resolution.width as f64 * self.scale_factor,
+            resolution.height as f64 * self.scale_factor,
+        )
+    }
+
+    /// Coordinates of a pixel at full precision
+    pub fn point(&self, x: f64, y: f64, resolution: Resolution) -> (Fixed, Fixed) {
+        let bits = self.bits();
+        let (dx, dy) = self.pixel_offset(x, y, resolution);
+        let (dx, dy) = self.rotate(dx, dy);
+        (
+            &self.center.0 + &Fixed::from_f64(dx, bits),
+            &self.center.1 + &Fixed::from_f64(dy, bits),
+        )
+    }
+
+    /// Turns an offset along the axes of the screen into an offset in the plane
+    pub fn rotate(&self, dx: f64, dy: f64) -> (f64, f64) {
+        if self.rotation == 0.0 {
+            return (dx, dy);
+        }
+        // The y axis points down on the screen, so counterclockwise is a negative angle
+        let (sin, cos) = (-self.rotation.to_radians()).sin_cos();
+        (dx * cos - dy * sin, dx * sin + dy * cos)
+    }
+
+    pub fn zoom(&mut self, factor: f64) {
+        self.scale_factor /= factor;
+        self.refine();
+    }
+
+    /// Zooms while keeping the point under a pixel in place
+    pub fn zoom_at(&mut self, factor: f64, x: f64, y: f64, resolution: Resolution) {
+        let (dx, dy) = self.pixel_offset(x, y, resolution);
+        let moved = 1.0 - 1.0 / factor;
+        self.translate(dx * moved, dy * moved);
+        self.zoom(factor);
+    }
+
+    /// Zooms so a rectangle between two corner pixels fills the view
+    pub fn zoom_box(&mut self, start: (f64, f64), end: (f64, f64), resolution: Resolution) {
+        let (dx, dy) =
+            self.pixel_offset((start.0 + end.0) / 2.0, (start.1 + end.1) / 2.0, resolution);
+        self.translate(dx, dy);
+        let factor = (resolution.width as f64 / (end.0 - start.0).abs())
+            .min(resolution.height as f64 / (end.1 - start.1).abs());
+        self.zoom(factor);
+    }
+
+    /// Offset of a pixel from the centre along the axes of the screen
+    fn pixel_offset(&self, x: f64, y: f64, resolution: Resolution) -> (f64, f64) {
+        (
+            (x - resolution.width as f64 / 2.0) * self.scale_factor,
+            (y - resolution.height as f64 / 2.0) * self.scale_factor,
+        )
+    }
+
+    /// Zooms so the view spans the given width of the plane at the given resolution
+    pub fn fit_width(&mut self, width: f64, resolution: Resolution) {
+        self.scale_factor = width / resolution.width as f64;
+        self.refine();
+    }
+
+    /// Moves the view along the axes of the screen
+    pub fn translate(&mut self, dx: f64, dy: f64) {
+        let (dx, dy) = self.rotate(dx, dy);
+        let bits = self.bits();
+        self.center = (
+            &self.center.0 + &Fixed::from_f64(dx, bits),
+            &self.center.1 + &Fixed::from_f64(dy, bits),
+        );
+    }
+
+    /// Whole number of pixels along the axes of the screen which moved this view to another one,
+    /// if that's all that changed
+    pub fn pixel_shift(&self, to: &View) -> Option<(isize, isize)> {
+        if self.scale_factor != to.scale_factor || self.rotation != to.rotation {
+            return None;
+        }
+        let dx = (&to.center.0 - &self.center.0).to_f64() / self.scale_factor;
+        let dy = (&to.center.1 - &self.center.1).to_f64() / self.scale_factor;
+        // Rotating back the other way gives the offset along the screen
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let (dx, dy) = (dx * cos - dy * sin, dx * sin + dy * cos);
+
+        let (x, y) = (dx.round(), dy.round());
+        if (dx - x).abs() > 1e-3 || (dy - y).abs() > 1e-3 {
+            return None;
+        }
+        Some((x as isize, y as isize))
+    }
+
+    fn refine(&mut self) {
+        let bits = self.bits();
+        if self.center.0.bits() < bits || self.center.1.bits() < bits {
+            self.center = (self.center.0.with_bits(bits), self.center.1.with_bits(bits));
+        }
+    }
+}
+
+fn area(config: &Config) -> Area {
+    match config.fractal {
+        Fractal::Mandelbrot => config.escape_time().area(),
+        Fractal::Julia => Area {
+            x_start: -1.75,
+            x_end: 1.75,
+            y_start: -1.0,
+            y_end: 1.0,
+        },
+    }
+}
+
+fn unzoomed_scale_factor(config: &Config) -> f64 {
+    let area = area(config);
+    let resolution = config.preview.resolution;
+    ((area.x_end - area.x_start) / resolution.width as f64)
+        .max((area.y_end - area.y_start) / resolution.height as f64)
+}